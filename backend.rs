@@ -0,0 +1,7 @@
+//! The heartbeats-simple implementation: native by default, or pure Rust with the `pure-rust`
+//! feature.
+
+#[cfg(feature = "pure-rust")]
+pub use pure::*;
+#[cfg(not(feature = "pure-rust"))]
+pub use heartbeats_simple_sys::*;
@@ -0,0 +1,90 @@
+//! Summarize heartbeat logs: totals, and distributions of instant rates.
+
+extern crate heartbeats_simple;
+
+use heartbeats_simple::log::analyze::{LogSummary, SummaryFormat};
+use heartbeats_simple::log::read::{AnyLogReader, ReadError};
+use std::env;
+use std::io::{self, BufRead, Write};
+use std::process;
+
+const USAGE: &str = "\
+Usage: hbs-analyze [--format text|csv|json] LOG...
+
+Summarize heartbeat logs of any variant and format (fixed-width, CSV, JSON Lines, or binary).
+Use '-' to read a log from standard input.
+
+Options:
+  -f, --format FORMAT  output format: text (default), csv, or json
+  -h, --help           print this help";
+
+fn usage_error(msg: &str) -> ! {
+    eprintln!("hbs-analyze: {}\n\n{}", msg, USAGE);
+    process::exit(2);
+}
+
+fn summarize<R: BufRead>(reader: R) -> Result<LogSummary, ReadError> {
+    let reader = AnyLogReader::new(reader)?;
+    LogSummary::from_records(reader.variant(), reader)
+}
+
+fn main() {
+    let mut format = SummaryFormat::Text;
+    let mut logs = Vec::new();
+    let mut args = env::args().skip(1);
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "-h" | "--help" => {
+                println!("{}", USAGE);
+                return;
+            }
+            "-f" | "--format" => {
+                format = match args.next().as_deref() {
+                    Some("text") => SummaryFormat::Text,
+                    Some("csv") => SummaryFormat::Csv,
+                    Some("json") => SummaryFormat::Json,
+                    Some(f) => usage_error(&format!("unknown format '{}'", f)),
+                    None => usage_error("missing format"),
+                };
+            }
+            a if a.starts_with('-') && a != "-" => usage_error(&format!("unknown option '{}'", a)),
+            _ => logs.push(arg),
+        }
+    }
+    if logs.is_empty() {
+        usage_error("no logs given");
+    }
+
+    let stdout = io::stdout();
+    let mut out = stdout.lock();
+    let mut failed = false;
+    let mut res = format.write_header(&mut out);
+    for (i, log) in logs.iter().enumerate() {
+        let summary = if log == "-" {
+            let stdin = io::stdin();
+            let lock = stdin.lock();
+            summarize(lock)
+        } else {
+            AnyLogReader::open(log).and_then(|r| LogSummary::from_records(r.variant(), r))
+        };
+        match summary {
+            Ok(s) => {
+                if format == SummaryFormat::Text && i > 0 {
+                    res = res.and_then(|_| writeln!(out));
+                }
+                res = res.and_then(|_| format.write_summary(log, &s, &mut out));
+            }
+            Err(e) => {
+                eprintln!("hbs-analyze: {}: {}", log, e);
+                failed = true;
+            }
+        }
+    }
+    if let Err(e) = res.and_then(|_| out.flush()) {
+        eprintln!("hbs-analyze: {}", e);
+        failed = true;
+    }
+    if failed {
+        process::exit(1);
+    }
+}
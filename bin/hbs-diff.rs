@@ -0,0 +1,110 @@
+//! Compare a baseline heartbeat log against a new one, failing on regressions.
+
+extern crate heartbeats_simple;
+
+use heartbeats_simple::log::LogRecord;
+use heartbeats_simple::log::diff::{Alignment, DiffFormat, LogDiff, Thresholds};
+use heartbeats_simple::log::read::{AnyLogReader, ReadError};
+use std::env;
+use std::io::{self, Write};
+use std::process;
+
+const USAGE: &str = "\
+Usage: hbs-diff [OPTIONS] BASELINE CANDIDATE
+
+Compare the performance, power, and accuracy rate of two heartbeat logs, in any format
+(fixed-width, CSV, JSON Lines, or binary). Exits with status 1 if a threshold is exceeded by a
+statistically significant change (Welch's t-test on instant rates), or 2 on errors.
+
+Options:
+  -a, --align index|tag         pair records by position (default) or by tag
+      --max-perf-drop PCT       fail if performance drops by more than PCT percent
+      --max-power-increase PCT  fail if power increases by more than PCT percent
+      --max-accuracy-drop PCT   fail if accuracy rate drops by more than PCT percent
+      --alpha ALPHA             significance level (default 0.05)
+  -f, --format text|json        output format (default text)
+  -h, --help                    print this help";
+
+fn usage_error(msg: &str) -> ! {
+    eprintln!("hbs-diff: {}\n\n{}", msg, USAGE);
+    process::exit(2);
+}
+
+fn parse_number(option: &str, value: Option<String>) -> f64 {
+    let value = value.unwrap_or_else(|| usage_error(&format!("missing value for {}", option)));
+    match value.parse::<f64>() {
+        Ok(v) if v >= 0.0 => v,
+        _ => usage_error(&format!("invalid value for {}: '{}'", option, value)),
+    }
+}
+
+fn read_log(path: &str) -> Vec<LogRecord> {
+    let res: Result<Vec<LogRecord>, ReadError> =
+        AnyLogReader::open(path).and_then(|reader| reader.collect());
+    res.unwrap_or_else(|e| {
+        eprintln!("hbs-diff: {}: {}", path, e);
+        process::exit(2);
+    })
+}
+
+fn main() {
+    let mut alignment = Alignment::Index;
+    let mut format = DiffFormat::Text;
+    let mut thresholds = Thresholds::new();
+    let mut logs = Vec::new();
+    let mut args = env::args().skip(1);
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "-h" | "--help" => {
+                println!("{}", USAGE);
+                return;
+            }
+            "-a" | "--align" => {
+                alignment = match args.next().as_deref() {
+                    Some("index") => Alignment::Index,
+                    Some("tag") => Alignment::Tag,
+                    Some(a) => usage_error(&format!("unknown alignment '{}'", a)),
+                    None => usage_error("missing alignment"),
+                };
+            }
+            "-f" | "--format" => {
+                format = match args.next().as_deref() {
+                    Some("text") => DiffFormat::Text,
+                    Some("json") => DiffFormat::Json,
+                    Some(f) => usage_error(&format!("unknown format '{}'", f)),
+                    None => usage_error("missing format"),
+                };
+            }
+            "--max-perf-drop" => {
+                thresholds = thresholds.perf_drop(parse_number(&arg, args.next()) / 100.0);
+            }
+            "--max-power-increase" => {
+                thresholds = thresholds.power_increase(parse_number(&arg, args.next()) / 100.0);
+            }
+            "--max-accuracy-drop" => {
+                let pct = parse_number(&arg, args.next());
+                thresholds = thresholds.accuracy_rate_drop(pct / 100.0);
+            }
+            "--alpha" => thresholds = thresholds.alpha(parse_number(&arg, args.next())),
+            a if a.starts_with('-') => usage_error(&format!("unknown option '{}'", a)),
+            _ => logs.push(arg),
+        }
+    }
+    if logs.len() != 2 {
+        usage_error("expected a baseline log and a candidate log");
+    }
+
+    let baseline = read_log(&logs[0]);
+    let candidate = read_log(&logs[1]);
+    let diff = LogDiff::new(&baseline, &candidate, alignment);
+    let regressions = diff.regressions(&thresholds);
+    let stdout = io::stdout();
+    let mut out = stdout.lock();
+    if let Err(e) = format.write_diff(&diff, &regressions, &mut out).and_then(|_| out.flush()) {
+        eprintln!("hbs-diff: {}", e);
+        process::exit(2);
+    }
+    if !regressions.is_empty() {
+        process::exit(1);
+    }
+}
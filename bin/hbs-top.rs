@@ -0,0 +1,158 @@
+//! Monitor heartbeats published by running applications, refreshing in the terminal.
+
+extern crate heartbeats_simple;
+#[cfg(unix)]
+extern crate libc;
+
+use heartbeats_simple::PublishedReader;
+use heartbeats_simple::log::LogRecord;
+use heartbeats_simple::log::analyze::sparkline;
+use std::env;
+use std::io::{self, Write};
+use std::process;
+use std::thread;
+use std::time::Duration;
+
+const USAGE: &str = "\
+Usage: hbs-top [OPTIONS] STATE...
+
+Show live window and instant performance, power, and accuracy rate for applications that
+publish their heartbeats (see `publish_to`), with sparklines of the recent window.
+Each STATE is a file an application publishes to, e.g. under /dev/shm.
+
+Options:
+  -n, --interval SECONDS  time between refreshes (default 1)
+  -w, --width COLUMNS     maximum sparkline width (default 60)
+      --once              print once and exit, without clearing the screen
+  -h, --help              print this help";
+
+/// Clear the screen and move the cursor home.
+const CLEAR: &str = "\x1b[H\x1b[2J";
+
+fn usage_error(msg: &str) -> ! {
+    eprintln!("hbs-top: {}\n\n{}", msg, USAGE);
+    process::exit(2);
+}
+
+/// A monitored application's published state, opened once it exists and reopened when the
+/// application restarts.
+struct Monitor {
+    path: String,
+    reader: Option<PublishedReader>,
+}
+
+#[cfg(unix)]
+fn is_running(pid: u32) -> bool {
+    let rc = unsafe { libc::kill(pid as libc::pid_t, 0) };
+    rc == 0 || io::Error::last_os_error().raw_os_error() != Some(libc::ESRCH)
+}
+
+#[cfg(not(unix))]
+fn is_running(_pid: u32) -> bool {
+    true
+}
+
+fn rate_line(name: &str, window: f64, instant: f64, recent: &[f64]) -> String {
+    format!("  {:<9} window {:>16.6}  instant {:>16.6}  {}\n",
+            name, window, instant, sparkline(recent))
+}
+
+impl Monitor {
+    fn render(&mut self, width: usize) -> String {
+        if self.reader.as_ref().is_some_and(PublishedReader::is_stale) {
+            self.reader = None;
+        }
+        if self.reader.is_none() {
+            match PublishedReader::open(&self.path) {
+                Ok(r) => self.reader = Some(r),
+                // the application may not have started yet
+                Err(e) => return format!("{}  (waiting: {})\n", self.path, e),
+            }
+        }
+        let reader = self.reader.as_ref().unwrap();
+        let state = match reader.read() {
+            Ok(state) => state,
+            Err(e) => return format!("{}  (error: {})\n", self.path, e),
+        };
+        let status = if is_running(reader.pid()) { "" } else { "  (exited)" };
+        let mut text = format!("{}  pid {}  {}  window {}  heartbeats {}{}\n",
+                               self.path, reader.pid(), reader.variant().name(),
+                               reader.window_size(), state.updates, status);
+        let latest = match state.latest {
+            Some(latest) => latest,
+            None => return text,
+        };
+        let recent = &state.window[state.window.len().saturating_sub(width)..];
+        let series = |f: &dyn Fn(&LogRecord) -> Option<f64>| -> Vec<f64> {
+            recent.iter().map(|r| f(r).unwrap_or(f64::NAN)).collect()
+        };
+        text.push_str(&rate_line("perf", latest.window_perf, latest.instant_perf,
+                                 &series(&|r| Some(r.instant_perf))));
+        if let Some(p) = latest.power {
+            text.push_str(&rate_line("power", p.window_power, p.instant_power,
+                                     &series(&|r| r.power.map(|p| p.instant_power))));
+        }
+        if let Some(a) = latest.accuracy {
+            text.push_str(&rate_line("accuracy", a.window_accuracy_rate, a.instant_accuracy_rate,
+                                     &series(&|r| r.accuracy.map(|a| a.instant_accuracy_rate))));
+        }
+        text
+    }
+}
+
+fn main() {
+    let mut interval = Duration::from_secs(1);
+    let mut width = 60;
+    let mut once = false;
+    let mut monitors = Vec::new();
+    let mut args = env::args().skip(1);
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "-h" | "--help" => {
+                println!("{}", USAGE);
+                return;
+            }
+            "-n" | "--interval" => {
+                interval = match args.next().map(|s| s.parse::<f64>()) {
+                    Some(Ok(secs)) if secs > 0.0 && secs.is_finite() => {
+                        Duration::from_secs_f64(secs)
+                    }
+                    _ => usage_error("invalid interval"),
+                };
+            }
+            "-w" | "--width" => {
+                width = match args.next().map(|s| s.parse::<usize>()) {
+                    Some(Ok(w)) if w > 0 => w,
+                    _ => usage_error("invalid width"),
+                };
+            }
+            "--once" => once = true,
+            a if a.starts_with('-') => usage_error(&format!("unknown option '{}'", a)),
+            _ => monitors.push(Monitor { path: arg, reader: None }),
+        }
+    }
+    if monitors.is_empty() {
+        usage_error("no published state given");
+    }
+
+    let stdout = io::stdout();
+    loop {
+        let mut screen = if once { String::new() } else { CLEAR.to_string() };
+        for (i, m) in monitors.iter_mut().enumerate() {
+            if i > 0 {
+                screen.push('\n');
+            }
+            screen.push_str(&m.render(width));
+        }
+        let mut out = stdout.lock();
+        if let Err(e) = out.write_all(screen.as_bytes()).and_then(|_| out.flush()) {
+            // e.g. a closed pipe
+            eprintln!("hbs-top: {}", e);
+            process::exit(1);
+        }
+        if once {
+            return;
+        }
+        thread::sleep(interval);
+    }
+}
@@ -0,0 +1,162 @@
+#[cfg(unix)]
+use libc;
+#[cfg(not(unix))]
+use std::sync::OnceLock;
+#[cfg(not(unix))]
+use std::time::Instant;
+#[cfg(test)]
+use std::sync::Arc;
+#[cfg(test)]
+use std::sync::atomic::{AtomicU64, Ordering};
+
+/// A source of heartbeat timestamps.
+pub trait Clock {
+    /// Get the current time in nanoseconds.
+    fn now(&self) -> u64;
+
+    /// A short name identifying the clock source.
+    fn name(&self) -> &'static str;
+}
+
+#[cfg(unix)]
+fn clock_gettime_ns(clk_id: libc::clockid_t) -> u64 {
+    let mut ts = libc::timespec { tv_sec: 0, tv_nsec: 0 };
+    // only fails for unsupported clock IDs, which are excluded at compile time
+    unsafe {
+        libc::clock_gettime(clk_id, &mut ts);
+    }
+    ts.tv_sec as u64 * 1_000_000_000 + ts.tv_nsec as u64
+}
+
+/// Monotonic time (`CLOCK_MONOTONIC`), subject to NTP frequency adjustments.
+///
+/// This is the default heartbeat clock.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct MonotonicClock;
+
+impl Clock for MonotonicClock {
+    #[cfg(unix)]
+    fn now(&self) -> u64 {
+        clock_gettime_ns(libc::CLOCK_MONOTONIC)
+    }
+
+    #[cfg(not(unix))]
+    fn now(&self) -> u64 {
+        static EPOCH: OnceLock<Instant> = OnceLock::new();
+        EPOCH.get_or_init(Instant::now).elapsed().as_nanos() as u64
+    }
+
+    fn name(&self) -> &'static str {
+        "CLOCK_MONOTONIC"
+    }
+}
+
+/// Raw hardware-based monotonic time (`CLOCK_MONOTONIC_RAW`), not subject to NTP adjustments.
+#[cfg(any(target_os = "linux", target_os = "android", target_os = "macos", target_os = "ios"))]
+#[derive(Debug, Clone, Copy, Default)]
+pub struct MonotonicRawClock;
+
+#[cfg(any(target_os = "linux", target_os = "android", target_os = "macos", target_os = "ios"))]
+impl Clock for MonotonicRawClock {
+    fn now(&self) -> u64 {
+        clock_gettime_ns(libc::CLOCK_MONOTONIC_RAW)
+    }
+
+    fn name(&self) -> &'static str {
+        "CLOCK_MONOTONIC_RAW"
+    }
+}
+
+/// CPU time consumed by all threads in the process (`CLOCK_PROCESS_CPUTIME_ID`).
+#[cfg(unix)]
+#[derive(Debug, Clone, Copy, Default)]
+pub struct ProcessCpuClock;
+
+#[cfg(unix)]
+impl Clock for ProcessCpuClock {
+    fn now(&self) -> u64 {
+        clock_gettime_ns(libc::CLOCK_PROCESS_CPUTIME_ID)
+    }
+
+    fn name(&self) -> &'static str {
+        "CLOCK_PROCESS_CPUTIME_ID"
+    }
+}
+
+/// CPU time consumed by the calling thread (`CLOCK_THREAD_CPUTIME_ID`).
+///
+/// Only meaningful if the heartbeat is always issued from the same thread.
+#[cfg(unix)]
+#[derive(Debug, Clone, Copy, Default)]
+pub struct ThreadCpuClock;
+
+#[cfg(unix)]
+impl Clock for ThreadCpuClock {
+    fn now(&self) -> u64 {
+        clock_gettime_ns(libc::CLOCK_THREAD_CPUTIME_ID)
+    }
+
+    fn name(&self) -> &'static str {
+        "CLOCK_THREAD_CPUTIME_ID"
+    }
+}
+
+/// A clock that only advances when told to.
+#[cfg(test)]
+#[derive(Clone, Default)]
+pub(crate) struct TestClock(Arc<AtomicU64>);
+
+#[cfg(test)]
+impl TestClock {
+    pub(crate) fn advance(&self, ns: u64) {
+        self.0.fetch_add(ns, Ordering::SeqCst);
+    }
+}
+
+#[cfg(test)]
+impl Clock for TestClock {
+    fn now(&self) -> u64 {
+        self.0.load(Ordering::SeqCst)
+    }
+
+    fn name(&self) -> &'static str {
+        "test"
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn spin(clock: &dyn Clock) {
+        let start = clock.now();
+        while clock.now() == start {}
+    }
+
+    #[test]
+    fn test_monotonic() {
+        let clock = MonotonicClock;
+        let start = clock.now();
+        spin(&clock);
+        assert!(clock.now() > start);
+    }
+
+    #[cfg(any(target_os = "linux", target_os = "android", target_os = "macos", target_os = "ios"))]
+    #[test]
+    fn test_monotonic_raw() {
+        let clock = MonotonicRawClock;
+        let start = clock.now();
+        spin(&clock);
+        assert!(clock.now() > start);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_cpu_time() {
+        let (process, thread) = (ProcessCpuClock, ThreadCpuClock);
+        let (p_start, t_start) = (process.now(), thread.now());
+        spin(&thread);
+        assert!(process.now() > p_start);
+        assert!(thread.now() > t_start);
+    }
+}
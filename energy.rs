@@ -0,0 +1,195 @@
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+#[cfg(test)]
+use std::sync::Arc;
+#[cfg(test)]
+use std::sync::atomic::{AtomicU64, Ordering};
+
+/// The default location of the Linux powercap sysfs interface.
+pub const POWERCAP_ROOT: &str = "/sys/class/powercap";
+
+/// A source of energy readings for power heartbeats.
+pub trait EnergyReader {
+    /// Read the current energy counter value, in microjoules.
+    fn read_energy(&mut self) -> io::Result<u64>;
+
+    /// The range of the energy counter, if it wraps back to 0 on reaching this value.
+    fn max_energy(&self) -> Option<u64> {
+        None
+    }
+}
+
+/// Get the energy consumed between counter readings `start` and `end`, where the counter wraps
+/// back to 0 on reaching `range`.
+///
+/// `start` may be a previously corrected reading, i.e. greater than `range`.
+pub fn wrapped_energy_delta(start: u64, end: u64, range: u64) -> u64 {
+    if end >= start || range == 0 {
+        end.wrapping_sub(start)
+    } else {
+        let (start, end) = (start % range, end % range);
+        // without overflowing when range is near u64::MAX
+        if end >= start { end - start } else { range - start + end }
+    }
+}
+
+fn read_u64(path: &Path) -> io::Result<u64> {
+    fs::read_to_string(path)?
+        .trim()
+        .parse()
+        .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+}
+
+/// Reads energy from Linux powercap (Intel RAPL) zones.
+///
+/// The energy of all top-level `intel-rapl:N` zones (i.e., packages) is summed; subzones like
+/// `intel-rapl:N:M` are already included in their parent's energy.
+/// Each zone's counter wraps at its `max_energy_range_uj`, so the reader accumulates corrected
+/// deltas and the reported energy never wraps.
+#[derive(Debug, Clone)]
+pub struct PowercapEnergyReader {
+    zones: Vec<PathBuf>,
+    ranges: Vec<Option<u64>>,
+    last: Vec<u64>,
+    total: u64,
+}
+
+impl PowercapEnergyReader {
+    /// Find the RAPL zones in `/sys/class/powercap`.
+    pub fn new() -> io::Result<PowercapEnergyReader> {
+        PowercapEnergyReader::with_root(POWERCAP_ROOT)
+    }
+
+    /// Find the RAPL zones in a powercap directory tree rooted at `root`.
+    pub fn with_root<P: AsRef<Path>>(root: P) -> io::Result<PowercapEnergyReader> {
+        let mut zones = Vec::new();
+        for entry in fs::read_dir(root)? {
+            let entry = entry?;
+            let name = entry.file_name();
+            let name = name.to_string_lossy();
+            if name.starts_with("intel-rapl:") && name.matches(':').count() == 1 {
+                zones.push(entry.path());
+            }
+        }
+        if zones.is_empty() {
+            return Err(io::Error::new(io::ErrorKind::NotFound, "No powercap RAPL zones found"));
+        }
+        zones.sort();
+        let ranges = zones.iter().map(|z| read_u64(&z.join("max_energy_range_uj")).ok()).collect();
+        let last = zones.iter()
+            .map(|z| read_u64(&z.join("energy_uj")))
+            .collect::<io::Result<Vec<_>>>()?;
+        let total = last.iter().sum();
+        Ok(PowercapEnergyReader { zones, ranges, last, total })
+    }
+
+    /// The zone directories being read.
+    pub fn zones(&self) -> &[PathBuf] {
+        &self.zones
+    }
+}
+
+impl EnergyReader for PowercapEnergyReader {
+    fn read_energy(&mut self) -> io::Result<u64> {
+        for (i, zone) in self.zones.iter().enumerate() {
+            let energy = read_u64(&zone.join("energy_uj"))?;
+            self.total += match self.ranges[i] {
+                Some(range) => wrapped_energy_delta(self.last[i], energy, range),
+                None => energy.saturating_sub(self.last[i]),
+            };
+            self.last[i] = energy;
+        }
+        Ok(self.total)
+    }
+}
+
+/// An energy reader that only advances when told to, optionally wrapping at a maximum value.
+#[cfg(test)]
+#[derive(Clone, Default)]
+pub(crate) struct TestEnergy(Arc<AtomicU64>, Option<u64>);
+
+#[cfg(test)]
+impl TestEnergy {
+    pub(crate) fn wrapping(max: u64) -> TestEnergy {
+        TestEnergy(Arc::default(), Some(max))
+    }
+
+    pub(crate) fn advance(&self, uj: u64) {
+        self.0.fetch_add(uj, Ordering::SeqCst);
+    }
+}
+
+#[cfg(test)]
+impl EnergyReader for TestEnergy {
+    fn read_energy(&mut self) -> io::Result<u64> {
+        let energy = self.0.load(Ordering::SeqCst);
+        Ok(self.1.map_or(energy, |max| energy % max))
+    }
+
+    fn max_energy(&self) -> Option<u64> {
+        self.1
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::env;
+    use std::process;
+
+    /// Create a fake powercap tree with the given zones and their `energy_uj` values.
+    fn fake_powercap(name: &str, zones: &[(&str, u64)]) -> PathBuf {
+        let root = env::temp_dir().join(format!("heartbeats-simple-powercap-{}-{}",
+                                                name, process::id()));
+        let _ = fs::remove_dir_all(&root);
+        for &(zone, energy) in zones {
+            fs::create_dir_all(root.join(zone)).unwrap();
+            fs::write(root.join(zone).join("energy_uj"), format!("{}\n", energy)).unwrap();
+        }
+        root
+    }
+
+    #[test]
+    fn test_powercap() {
+        let root = fake_powercap("read", &[("intel-rapl:0", 1000),
+                                           ("intel-rapl:0:0", 400),
+                                           ("intel-rapl:1", 2000)]);
+        let mut reader = PowercapEnergyReader::with_root(&root).unwrap();
+        assert_eq!(reader.zones().len(), 2);
+        assert_eq!(reader.read_energy().unwrap(), 3000);
+        fs::write(root.join("intel-rapl:1").join("energy_uj"), "2500\n").unwrap();
+        assert_eq!(reader.read_energy().unwrap(), 3500);
+        fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[test]
+    fn test_powercap_no_zones() {
+        let root = fake_powercap("empty", &[("intel-rapl-mmio:0", 1000)]);
+        assert!(PowercapEnergyReader::with_root(&root).is_err());
+        fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[test]
+    fn test_wrapped_energy_delta() {
+        assert_eq!(wrapped_energy_delta(100, 300, 1000), 200);
+        assert_eq!(wrapped_energy_delta(900, 100, 1000), 200);
+        // start was already corrected for a previous wrap
+        assert_eq!(wrapped_energy_delta(1100, 300, 1000), 200);
+        assert_eq!(wrapped_energy_delta(1900, 100, 1000), 200);
+        assert_eq!(wrapped_energy_delta(u64::MAX - 2, 5, u64::MAX), 7);
+    }
+
+    #[test]
+    fn test_powercap_wraparound() {
+        let root = fake_powercap("wrap", &[("intel-rapl:0", 9500)]);
+        fs::write(root.join("intel-rapl:0").join("max_energy_range_uj"), "10000\n").unwrap();
+        let mut reader = PowercapEnergyReader::with_root(&root).unwrap();
+        assert_eq!(reader.read_energy().unwrap(), 9500);
+        fs::write(root.join("intel-rapl:0").join("energy_uj"), "200\n").unwrap();
+        assert_eq!(reader.read_energy().unwrap(), 10200);
+        fs::write(root.join("intel-rapl:0").join("energy_uj"), "1200\n").unwrap();
+        assert_eq!(reader.read_energy().unwrap(), 11200);
+        fs::remove_dir_all(&root).unwrap();
+    }
+}
@@ -0,0 +1,60 @@
+use std::error::Error;
+use std::fmt;
+use std::io;
+
+/// Errors reported by heartbeats.
+#[derive(Debug)]
+pub enum HeartbeatError {
+    /// The native heartbeat failed to initialize, with the return code.
+    Init(i32),
+    /// The window size must be greater than 0.
+    InvalidWindowSize(usize),
+    /// An I/O error, e.g. while writing a log.
+    Io(io::Error),
+    /// A heartbeat was issued with invalid input.
+    InvalidInput(String),
+    /// Energy sampling was requested, but no energy reader is configured.
+    NoEnergyReader,
+}
+
+impl fmt::Display for HeartbeatError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            HeartbeatError::Init(rc) => {
+                write!(f, "Failed to initialize heartbeat (return code {})", rc)
+            }
+            HeartbeatError::InvalidWindowSize(ws) => write!(f, "Invalid window size: {}", ws),
+            HeartbeatError::Io(ref e) => write!(f, "Heartbeat I/O error: {}", e),
+            HeartbeatError::InvalidInput(ref msg) => write!(f, "Invalid heartbeat input: {}", msg),
+            HeartbeatError::NoEnergyReader => write!(f, "No energy reader configured"),
+        }
+    }
+}
+
+impl Error for HeartbeatError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match *self {
+            HeartbeatError::Io(ref e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+impl From<io::Error> for HeartbeatError {
+    fn from(e: io::Error) -> HeartbeatError {
+        HeartbeatError::Io(e)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_source() {
+        let e = HeartbeatError::from(io::Error::other("disk full"));
+        assert!(e.source().is_some());
+        assert_eq!(e.to_string(), "Heartbeat I/O error: disk full");
+        assert!(HeartbeatError::InvalidWindowSize(0).source().is_none());
+    }
+}
@@ -1,56 +1,123 @@
-use heartbeats_simple_sys::*;
+use backend::*;
 use std::mem;
 use std::io::{self, Write};
-use std::fs::File;
+use std::path::Path;
+use clock::{Clock, MonotonicClock};
+use error::HeartbeatError;
+use log::{BackgroundLog, Backpressure, LogFormat, LogOptions, LogRecord, LogVariant};
+use publish::Publisher;
+use hbs_common::{HeartbeatMonitor, HasAccuracy, LogSink, WindowRecords, WindowSummary};
+use validate::ValidationPolicy;
 
-pub use heartbeats_simple_sys::heartbeat_acc_context as HeartbeatAccContext;
-pub use heartbeats_simple_sys::heartbeat_acc_record as HeartbeatAccRecord;
-pub use heartbeats_simple_sys::heartbeat_acc_window_complete as HeartbeatAccWindowComplete;
+pub use backend::heartbeat_acc_context as HeartbeatAccContext;
+pub use backend::heartbeat_acc_record as HeartbeatAccRecord;
+pub use backend::heartbeat_acc_window_complete as HeartbeatAccWindowComplete;
+
+/// A Rust closure called when the window buffer is complete.
+pub type HeartbeatAccWindowCallback =
+    Box<dyn FnMut(&WindowSummary<HeartbeatAccContext, HeartbeatAccRecord>) + Send>;
+
+/// A snapshot of all of a `HeartbeatAcc`'s current statistics.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct HeartbeatAccStats {
+    pub window_size: u64,
+    pub user_tag: u64,
+    pub global_time: u64,
+    pub window_time: u64,
+    pub global_work: u64,
+    pub window_work: u64,
+    pub global_perf: f64,
+    pub window_perf: f64,
+    pub instant_perf: f64,
+    pub global_accuracy: u64,
+    pub window_accuracy: u64,
+    pub global_accuracy_rate: f64,
+    pub window_accuracy_rate: f64,
+    pub instant_accuracy_rate: f64,
+}
 
 /// Contains the Heartbeat and its window data buffer.
 pub struct HeartbeatAcc {
     pub hb: HeartbeatAccContext,
     pub hbr: Vec<HeartbeatAccRecord>,
-    pub log: Option<File>,
+    pub log: Option<LogSink>,
+    hwc: Option<HeartbeatAccWindowCallback>,
+    log_index: u64,
+    log_err: Option<io::Error>,
+    log_format: LogFormat,
+    background: Option<BackgroundLog>,
+    publisher: Option<Publisher>,
+    clock: Box<dyn Clock + Send>,
+    begin_time: u64,
+    validation: ValidationPolicy,
 }
 
 impl HeartbeatAcc {
-    /// Allocate and initialize a new `Heartbeat`.
+    /// Allocate and initialize a new `HeartbeatAcc`.
     pub fn new(window_size: usize,
                hwc_callback: HeartbeatAccWindowComplete,
-               mut log: Option<File>) -> Result<HeartbeatAcc, &'static str> {
+               log: Option<LogSink>) -> Result<HeartbeatAcc, HeartbeatError> {
+        HeartbeatAcc::with_log_format(window_size, hwc_callback, log, LogFormat::FixedWidth)
+    }
+
+    /// Allocate and initialize a new `HeartbeatAcc` that writes its log in `log_format`.
+    pub fn with_log_format(window_size: usize,
+                           hwc_callback: HeartbeatAccWindowComplete,
+                           log: Option<LogSink>,
+                           log_format: LogFormat) -> Result<HeartbeatAcc, HeartbeatError> {
+        let options = LogOptions::new().format(log_format);
+        HeartbeatAcc::with_log_options(window_size, hwc_callback, log, options)
+    }
+
+    /// Allocate and initialize a new `HeartbeatAcc` with the given log options, e.g. to start
+    /// the log with metadata.
+    pub fn with_log_options(window_size: usize,
+                            hwc_callback: HeartbeatAccWindowComplete,
+                            mut log: Option<LogSink>,
+                            options: LogOptions) -> Result<HeartbeatAcc, HeartbeatError> {
+        if window_size == 0 {
+            return Err(HeartbeatError::InvalidWindowSize(window_size));
+        }
         let mut hbr: Vec<HeartbeatAccRecord> = Vec::with_capacity(window_size);
         let hb: HeartbeatAccContext = unsafe {
             // must explicitly set size so we can read data later
             // (Rust isn't aware of native code modifying the buffer)
             hbr.set_len(window_size);
-            let mut hb = mem::uninitialized();
-            match heartbeat_acc_init(&mut hb,
+            let mut hb = mem::MaybeUninit::uninit();
+            match heartbeat_acc_init(hb.as_mut_ptr(),
                                      hbr.capacity() as u64,
                                      hbr.as_mut_ptr(),
                                      -1,
                                      hwc_callback) {
-                0 => hb,
-                _ => return Err("Failed to initialize heartbeat")
+                0 => hb.assume_init(),
+                rc => return Err(HeartbeatError::Init(rc))
             }
         };
+        let (log_format, clock, metadata) =
+            options.open(LogVariant::Acc, window_size, Box::new(MonotonicClock));
         // write header to log file if there is one
         if let Some(ref mut l) = log {
-            l.write_all(format!("{:6} {:6} \
-                         {:11} {:11} {:11} \
-                         {:15} {:15} {:20} {:20} \
-                         {:15} {:15} {:15} \
-                         {:15} {:15} {:15} \
-                         {:15} {:15} {}\n",
-                        "HB", "Tag",
-                        "Global_Work", "Window_Work", "Work",
-                        "Global_Time", "Window_Time", "Start_Time", "End_Time",
-                        "Global_Perf", "Window_Perf", "Instant_Perf",
-                        "Global_Acc", "Window_Acc", "Acc",
-                        "Global_Acc_Rate", "Window_Acc_Rate", "Instant_Acc_Rate")
-                        .as_bytes()).unwrap()
-        }
-        Ok(HeartbeatAcc { hb: hb, hbr: hbr, log: log, })
+            match metadata {
+                Some(ref m) => log_format.write_header_with_metadata(LogVariant::Acc, m, l)?,
+                None => log_format.write_header(LogVariant::Acc, l)?,
+            }
+        }
+        let begin_time = clock.now();
+        Ok(HeartbeatAcc {
+            hb,
+            hbr,
+            log,
+            hwc: None,
+            log_index: 0,
+            log_err: None,
+            log_format,
+            background: None,
+            publisher: None,
+            clock,
+            begin_time,
+            validation: ValidationPolicy::Reject,
+        })
     }
 
     /// Issue a heartbeat
@@ -63,36 +130,206 @@ impl HeartbeatAcc {
         unsafe {
             heartbeat_acc(&mut self.hb, tag, work, start_time, end_time, accuracy)
         }
+        if let Some(ref p) = self.publisher {
+            if let Some(r) = self.latest() {
+                p.publish(&LogRecord::from(r));
+            }
+        }
+        // the native code resets the buffer index when the window is complete
+        if self.hb.ws.buffer_index == 0 {
+            self.window_complete();
+        }
     }
 
-    fn write_log(r: &HeartbeatAccRecord, l: &mut File) -> io::Result<usize> {
-        l.write(format!("{:<6} {:<6} \
-                         {:<11} {:<11} {:<11} \
-                         {:<15} {:<15} {:<20} {:<20} \
-                         {:<15.6} {:<15.6} {:<15.6} \
-                         {:<15} {:<15} {:<15} \
-                         {:<15.6} {:<15.6} {:<.6}\n",
-                        r.id, r.user_tag,
-                        r.wd.global, r.wd.window, r.work,
-                        r.td.global, r.td.window, r.start_time, r.end_time,
-                        r.perf.global, r.perf.window, r.perf.instant,
-                        r.ad.global, r.ad.window, r.accuracy,
-                        r.acc.global, r.acc.window, r.acc.instant).as_bytes())
-    }
-
-    /// Rust-only function that logs the buffer (up to buffer_index) to a file.
-    pub fn log_to_buffer_index(&mut self) -> io::Result<()> {
-        match self.log {
-            Some(ref mut l) => {
-                for i in 0..self.hb.ws.buffer_index {
-                    match HeartbeatAcc::write_log(self.hbr.get(i as usize).unwrap(), l) {
-                        Ok(_) => (),
-                        Err(e) => return Err(e),
-                    }
-                }
-                l.flush()
+    /// Like `heartbeat`, but first validates that time doesn't go backwards, handling invalid
+    /// input according to the validation policy (see `set_validation_policy`).
+    pub fn try_heartbeat(&mut self,
+                         tag: u64,
+                         work: u64,
+                         start_time: u64,
+                         mut end_time: u64,
+                         accuracy: u64) -> Result<(), HeartbeatError> {
+        let prev_end_time = self.latest().map(|r| r.end_time);
+        self.validation.check_time(start_time, &mut end_time, prev_end_time)?;
+        self.heartbeat(tag, work, start_time, end_time, accuracy);
+        Ok(())
+    }
+
+    /// Set how `try_heartbeat` handles invalid input (`ValidationPolicy::Reject` by default).
+    pub fn set_validation_policy(&mut self, policy: ValidationPolicy) {
+        self.validation = policy;
+    }
+
+    /// Use `clock` to timestamp heartbeats issued by `begin`/`end` and `heartbeat_now`.
+    ///
+    /// Timestamps from different clocks aren't comparable, so set the clock before issuing any
+    /// heartbeats. This also restarts the current interval, as if `begin` was called.
+    pub fn set_clock<C: Clock + Send + 'static>(&mut self, clock: C) {
+        self.begin_time = clock.now();
+        self.clock = Box::new(clock);
+    }
+
+    /// Get the current time from the heartbeat's clock, in nanoseconds.
+    pub fn now(&self) -> u64 {
+        self.clock.now()
+    }
+
+    /// Start timing work to be reported by `end`.
+    pub fn begin(&mut self) {
+        self.begin_time = self.clock.now();
+    }
+
+    /// Issue a heartbeat for the work done since `begin` (or since the heartbeat was created).
+    pub fn end(&mut self, tag: u64, work: u64, accuracy: u64) {
+        let (start_time, end_time) = (self.begin_time, self.clock.now());
+        self.heartbeat(tag, work, start_time, end_time, accuracy);
+    }
+
+    /// Issue a heartbeat for the work done since the previous heartbeat ended (or since `begin`
+    /// if there is no previous heartbeat), timestamped by the heartbeat's clock.
+    pub fn heartbeat_now(&mut self, tag: u64, work: u64, accuracy: u64) {
+        let start_time = self.latest().map_or(self.begin_time, |r| r.end_time);
+        let end_time = self.clock.now();
+        self.heartbeat(tag, work, start_time, end_time, accuracy);
+    }
+
+    /// Start a scope that issues a heartbeat with `tag` when the returned guard is dropped.
+    ///
+    /// The heartbeat covers the time from now until the guard is dropped, so it's issued
+    /// even on early returns.
+    pub fn scope(&mut self, tag: u64) -> HeartbeatAccScope<'_> {
+        let start_time = self.now();
+        HeartbeatAccScope { hb: self, tag, work: 1, start_time, accuracy: 0 }
+    }
+
+    /// Register a closure to be called when the window buffer is complete.
+    ///
+    /// Unlike the `hwc_callback` passed to `new`, the closure may capture its own state.
+    pub fn set_window_callback<F>(&mut self, callback: F)
+        where F: FnMut(&WindowSummary<HeartbeatAccContext, HeartbeatAccRecord>) + Send + 'static {
+        self.hwc = Some(Box::new(callback));
+    }
+
+    fn window_complete(&mut self) {
+        // log records before they are overwritten, keeping errors for the next explicit flush
+        let window_size = self.hb.ws.window_size;
+        if let Err(e) = self.log_records(window_size) {
+            self.log_err.get_or_insert(e);
+        }
+        self.log_index = 0;
+        if let Some(ref mut cb) = self.hwc {
+            cb(&WindowSummary { context: &self.hb, records: &self.hbr });
+        }
+    }
+
+    /// Write any records not yet logged, up to (but not including) buffer index `end`.
+    fn log_records(&mut self, end: u64) -> io::Result<()> {
+        if let Some(ref bg) = self.background {
+            while self.log_index < end {
+                bg.send(LogRecord::from(&self.hbr[self.log_index as usize]));
+                self.log_index += 1;
+            }
+        } else if let Some(ref mut l) = self.log {
+            while self.log_index < end {
+                let r = LogRecord::from(&self.hbr[self.log_index as usize]);
+                self.log_format.write_record(&r, l)?;
+                self.log_index += 1;
             }
-            None => Ok(())
+        }
+        Ok(())
+    }
+
+    /// Rust-only function that logs any records not yet written (up to buffer_index) to a file,
+    /// then flushes it.
+    ///
+    /// Records are also logged automatically when the window buffer is complete and when the
+    /// heartbeat is dropped, so each record is written exactly once.
+    /// Errors from automatic logging are reported by the next call to this function.
+    pub fn log_to_buffer_index(&mut self) -> Result<(), HeartbeatError> {
+        let buffer_index = self.hb.ws.buffer_index;
+        let res = self.log_records(buffer_index).and_then(|_| match self.log {
+            Some(ref mut l) => l.flush(),
+            None => self.background.as_ref().map_or(Ok(()), BackgroundLog::flush),
+        });
+        match self.log_err.take() {
+            Some(e) => Err(e.into()),
+            None => res.map_err(HeartbeatError::from),
+        }
+    }
+
+    /// Move the log to a background thread, which formats and writes records handed to it
+    /// through a queue of up to `capacity` records.
+    ///
+    /// Logging then never blocks on I/O, unless `backpressure` is `Backpressure::Block` and the
+    /// queue is full. `log_to_buffer_index` only requests a flush, and reports errors from the
+    /// background thread. The queue is drained when the heartbeat is dropped.
+    /// Does nothing if there is no log.
+    pub fn set_background_log(&mut self,
+                              capacity: usize,
+                              backpressure: Backpressure) -> Result<(), HeartbeatError> {
+        if let Some(log) = self.log.take() {
+            let bg = BackgroundLog::new(log, self.log_format, capacity, backpressure)?;
+            self.background = Some(bg);
+        }
+        Ok(())
+    }
+
+    /// The number of records the background log dropped because its queue was full.
+    pub fn dropped_log_records(&self) -> u64 {
+        self.background.as_ref().map_or(0, BackgroundLog::dropped)
+    }
+
+    /// Publish the current statistics and window to a memory-mapped file at `path` after every
+    /// heartbeat, so other local processes can observe them with `PublishedReader`.
+    ///
+    /// Records already in the window are published immediately.
+    pub fn publish_to<P: AsRef<Path>>(&mut self, path: P) -> Result<(), HeartbeatError> {
+        let window_size = self.hb.ws.window_size as usize;
+        let publisher = Publisher::create(path, LogVariant::Acc, window_size)?;
+        for r in self.records() {
+            publisher.publish(&LogRecord::from(r));
+        }
+        self.publisher = Some(publisher);
+        Ok(())
+    }
+
+    /// Iterate over the valid records in the window buffer, oldest first.
+    pub fn records(&self) -> WindowRecords<'_, HeartbeatAccRecord> {
+        WindowRecords::new(&self.hbr, self.hb.counter, self.hb.ws.buffer_index)
+    }
+
+    /// The most recent record, if any heartbeats have been issued.
+    pub fn latest(&self) -> Option<&HeartbeatAccRecord> {
+        self.records().next_back()
+    }
+
+    /// The number of valid records in the window buffer.
+    pub fn len(&self) -> usize {
+        self.records().len()
+    }
+
+    /// Whether no heartbeats have been issued yet.
+    pub fn is_empty(&self) -> bool {
+        self.hb.counter == 0
+    }
+
+    /// Get a snapshot of all the current statistics.
+    pub fn stats(&self) -> HeartbeatAccStats {
+        HeartbeatAccStats {
+            window_size: self.get_window_size(),
+            user_tag: self.get_user_tag(),
+            global_time: self.get_global_time(),
+            window_time: self.get_window_time(),
+            global_work: self.get_global_work(),
+            window_work: self.get_window_work(),
+            global_perf: self.get_global_perf(),
+            window_perf: self.get_window_perf(),
+            instant_perf: self.get_instant_perf(),
+            global_accuracy: self.get_global_accuracy(),
+            window_accuracy: self.get_window_accuracy(),
+            global_accuracy_rate: self.get_global_accuracy_rate(),
+            window_accuracy_rate: self.get_window_accuracy_rate(),
+            instant_accuracy_rate: self.get_instant_accuracy_rate(),
         }
     }
 
@@ -181,10 +418,121 @@ impl HeartbeatAcc {
     }
 }
 
+/// Issues a heartbeat when dropped, covering the time since the scope was started.
+///
+/// Created by `HeartbeatAcc::scope`.
+pub struct HeartbeatAccScope<'a> {
+    hb: &'a mut HeartbeatAcc,
+    tag: u64,
+    work: u64,
+    start_time: u64,
+    accuracy: u64,
+}
+
+impl<'a> HeartbeatAccScope<'a> {
+    /// Set the work completed in this scope (1 by default).
+    pub fn set_work(&mut self, work: u64) {
+        self.work = work;
+    }
+
+    /// Set the accuracy achieved in this scope (0 by default).
+    pub fn set_accuracy(&mut self, accuracy: u64) {
+        self.accuracy = accuracy;
+    }
+}
+
+impl<'a> Drop for HeartbeatAccScope<'a> {
+    fn drop(&mut self) {
+        let end_time = self.hb.now();
+        self.hb.heartbeat(self.tag, self.work, self.start_time, end_time, self.accuracy);
+    }
+}
+
+impl Drop for HeartbeatAcc {
+    fn drop(&mut self) {
+        // errors can't be reported here; call log_to_buffer_index first to check for them
+        let _ = self.log_to_buffer_index();
+    }
+}
+
+// The context's raw pointer refers to the window buffer, which is owned and moved along with it.
+unsafe impl Send for HeartbeatAcc {}
+
+impl HeartbeatMonitor for HeartbeatAcc {
+    fn log_to_buffer_index(&mut self) -> Result<(), HeartbeatError> {
+        HeartbeatAcc::log_to_buffer_index(self)
+    }
+
+    fn get_window_size(&self) -> u64 {
+        HeartbeatAcc::get_window_size(self)
+    }
+
+    fn get_user_tag(&self) -> u64 {
+        HeartbeatAcc::get_user_tag(self)
+    }
+
+    fn get_global_time(&self) -> u64 {
+        HeartbeatAcc::get_global_time(self)
+    }
+
+    fn get_window_time(&self) -> u64 {
+        HeartbeatAcc::get_window_time(self)
+    }
+
+    fn get_global_work(&self) -> u64 {
+        HeartbeatAcc::get_global_work(self)
+    }
+
+    fn get_window_work(&self) -> u64 {
+        HeartbeatAcc::get_window_work(self)
+    }
+
+    fn get_global_perf(&self) -> f64 {
+        HeartbeatAcc::get_global_perf(self)
+    }
+
+    fn get_window_perf(&self) -> f64 {
+        HeartbeatAcc::get_window_perf(self)
+    }
+
+    fn get_instant_perf(&self) -> f64 {
+        HeartbeatAcc::get_instant_perf(self)
+    }
+
+    fn as_accuracy(&self) -> Option<&dyn HasAccuracy> {
+        Some(self)
+    }
+}
+
+impl HasAccuracy for HeartbeatAcc {
+    fn get_global_accuracy(&self) -> u64 {
+        HeartbeatAcc::get_global_accuracy(self)
+    }
+
+    fn get_window_accuracy(&self) -> u64 {
+        HeartbeatAcc::get_window_accuracy(self)
+    }
+
+    fn get_global_accuracy_rate(&self) -> f64 {
+        HeartbeatAcc::get_global_accuracy_rate(self)
+    }
+
+    fn get_window_accuracy_rate(&self) -> f64 {
+        HeartbeatAcc::get_window_accuracy_rate(self)
+    }
+
+    fn get_instant_accuracy_rate(&self) -> f64 {
+        HeartbeatAcc::get_instant_accuracy_rate(self)
+    }
+}
+
 #[cfg(test)]
 mod test {
     use super::*;
+    use clock::TestClock;
+    use hbs_common::TestLog;
     use std::fs::File;
+    use std::sync::{Arc, Mutex};
 
     #[test]
     fn test_simple() {
@@ -202,7 +550,7 @@ mod test {
     #[test]
     fn test_callback() {
         static mut RECEIVED_CB: bool = false;
-        extern fn callback(_hb: *const HeartbeatAccContext) {
+        extern "C" fn callback(_hb: *const HeartbeatAccContext) {
             unsafe {
                 RECEIVED_CB = true;
             }
@@ -215,10 +563,169 @@ mod test {
         }
     }
 
+    #[test]
+    fn test_window_callback() {
+        let ids = Arc::new(Mutex::new(Vec::new()));
+        let cb_ids = ids.clone();
+        let mut hb = HeartbeatAcc::new(2, None, None).unwrap();
+        hb.set_window_callback(move |w| {
+            cb_ids.lock().unwrap().extend(w.records.iter().map(|r| r.id));
+        });
+        for tag in 0..5 {
+            hb.heartbeat(tag, 1, tag * 1000, (tag + 1) * 1000, 1);
+        }
+        assert_eq!(*ids.lock().unwrap(), vec![0, 1, 2, 3]);
+    }
+
     #[test]
     fn test_file() {
-        let mut hb = HeartbeatAcc::new(5, None, Some(File::create("foo.log").unwrap())).unwrap();
+        let mut hb = HeartbeatAcc::new(5, None, Some(Box::new(File::create("foo.log").unwrap()))).unwrap();
         hb.heartbeat(0, 1, 0, 1000, 1);
         hb.log_to_buffer_index().unwrap();
     }
+
+    #[test]
+    fn test_log_records_once() {
+        let log = TestLog::default();
+        {
+            let mut hb = HeartbeatAcc::new(2, None, log.sink()).unwrap();
+            for tag in 0..5 {
+                hb.heartbeat(tag, 1, tag * 1000, (tag + 1) * 1000, 1);
+                if tag == 2 {
+                    hb.log_to_buffer_index().unwrap();
+                    hb.log_to_buffer_index().unwrap();
+                }
+            }
+        }
+        let ids: Vec<u64> = log.contents().lines().skip(1)
+            .map(|l| l.split_whitespace().next().unwrap().parse().unwrap())
+            .collect();
+        assert_eq!(ids, vec![0, 1, 2, 3, 4]);
+    }
+
+    #[test]
+    fn test_log_format() {
+        let log = TestLog::default();
+        {
+            let mut hb = HeartbeatAcc::with_log_format(2, None, log.sink(), LogFormat::Csv).unwrap();
+            for tag in 0..3 {
+                hb.heartbeat(tag, 1, tag * 1000, (tag + 1) * 1000, 1);
+            }
+        }
+        let contents = log.contents();
+        let lines: Vec<&str> = contents.lines().collect();
+        assert_eq!(lines.len(), 4);
+        assert_eq!(lines[0], LogVariant::Acc.columns().join(","));
+        assert!(lines[3].starts_with("2,2,3,2,1,3000,2000,2000,3000,"));
+    }
+
+    #[test]
+    fn test_background_log() {
+        let log = TestLog::default();
+        {
+            let mut hb = HeartbeatAcc::new(4, None, log.sink()).unwrap();
+            hb.set_background_log(2, Backpressure::Block).unwrap();
+            for tag in 0..10 {
+                hb.heartbeat(tag, 1, tag * 1000, (tag + 1) * 1000, 1);
+            }
+            hb.log_to_buffer_index().unwrap();
+            assert_eq!(hb.dropped_log_records(), 0);
+        }
+        let ids: Vec<u64> = log.contents().lines().skip(1)
+            .map(|l| l.split_whitespace().next().unwrap().parse().unwrap())
+            .collect();
+        assert_eq!(ids, (0..10).collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn test_invalid_window_size() {
+        match HeartbeatAcc::new(0, None, None) {
+            Err(HeartbeatError::InvalidWindowSize(0)) => (),
+            _ => panic!("Expected InvalidWindowSize"),
+        }
+    }
+
+    #[test]
+    fn test_records() {
+        let mut hb = HeartbeatAcc::new(3, None, None).unwrap();
+        assert!(hb.is_empty());
+        assert!(hb.latest().is_none());
+        for tag in 0..2 {
+            hb.heartbeat(tag, 1, tag * 1000, (tag + 1) * 1000, 1);
+        }
+        assert_eq!(hb.records().map(|r| r.id).collect::<Vec<_>>(), vec![0, 1]);
+        for tag in 2..5 {
+            hb.heartbeat(tag, 1, tag * 1000, (tag + 1) * 1000, 1);
+        }
+        assert_eq!(hb.records().map(|r| r.user_tag).collect::<Vec<_>>(), vec![2, 3, 4]);
+        assert_eq!(hb.latest().unwrap().id, 4);
+        assert_eq!(hb.len(), 3);
+    }
+
+    #[test]
+    fn test_stats() {
+        let mut hb = HeartbeatAcc::new(5, None, None).unwrap();
+        hb.heartbeat(7, 1, 0, 1000, 1);
+        let stats = hb.stats();
+        assert_eq!(stats.user_tag, 7);
+        assert_eq!(stats.window_size, 5);
+        assert_eq!(stats.global_work, hb.get_global_work());
+        assert_eq!(stats.instant_perf, hb.get_instant_perf());
+        assert_eq!(stats.global_accuracy_rate, hb.get_global_accuracy_rate());
+        assert_eq!(stats, hb.stats().clone());
+    }
+
+    #[test]
+    fn test_clock() {
+        let clock = TestClock::default();
+        let mut hb = HeartbeatAcc::new(5, None, None).unwrap();
+        hb.set_clock(clock.clone());
+        clock.advance(1000);
+        hb.heartbeat_now(0, 1, 1);
+        clock.advance(500);
+        hb.begin();
+        clock.advance(2000);
+        hb.end(1, 1, 1);
+        clock.advance(100);
+        hb.heartbeat_now(2, 1, 1);
+        let times: Vec<_> = hb.records().map(|r| (r.start_time, r.end_time)).collect();
+        assert_eq!(times, vec![(0, 1000), (1500, 3500), (3500, 3600)]);
+    }
+
+    #[test]
+    fn test_scope() {
+        fn work(hb: &mut HeartbeatAcc, clock: &TestClock, fail: bool) -> Result<(), ()> {
+            let mut scope = hb.scope(0);
+            clock.advance(1000);
+            scope.set_work(3);
+            scope.set_accuracy(2);
+            if fail {
+                return Err(());
+            }
+            Ok(())
+        }
+
+        let clock = TestClock::default();
+        let mut hb = HeartbeatAcc::new(5, None, None).unwrap();
+        hb.set_clock(clock.clone());
+        work(&mut hb, &clock, false).unwrap();
+        work(&mut hb, &clock, true).unwrap_err();
+        assert_eq!(hb.len(), 2);
+        let r = hb.latest().unwrap();
+        assert_eq!((r.start_time, r.end_time), (1000, 2000));
+        assert_eq!(r.work, 3);
+        assert_eq!(r.accuracy, 2);
+    }
+
+    #[test]
+    fn test_try_heartbeat() {
+        let mut hb = HeartbeatAcc::new(5, None, None).unwrap();
+        hb.try_heartbeat(0, 1, 0, 1000, 1).unwrap();
+        assert!(hb.try_heartbeat(1, 1, 2000, 1500, 1).is_err());
+        assert!(hb.try_heartbeat(1, 1, 500, 900, 1).is_err());
+        assert_eq!(hb.len(), 1);
+        hb.set_validation_policy(ValidationPolicy::Clamp);
+        hb.try_heartbeat(1, 1, 2000, 1500, 1).unwrap();
+        assert_eq!(hb.latest().unwrap().end_time, 2000);
+    }
 }
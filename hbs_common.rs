@@ -0,0 +1,217 @@
+use std::io::Write;
+use std::iter::Chain;
+use std::slice;
+use error::HeartbeatError;
+#[cfg(test)]
+use std::io;
+#[cfg(test)]
+use std::sync::{Arc, Mutex};
+
+/// A destination for heartbeat logs, e.g. a `File`, `io::stdout()`, or an in-memory `Vec<u8>`.
+pub type LogSink = Box<dyn Write + Send>;
+
+/// Functionality common to all heartbeat variants.
+///
+/// Allows instrumentation code to accept any heartbeat type and query performance data, and
+/// optionally accuracy or power data when the variant tracks it.
+pub trait HeartbeatMonitor {
+    /// Logs any records not yet written (up to buffer_index) to the log file, if there is one.
+    fn log_to_buffer_index(&mut self) -> Result<(), HeartbeatError>;
+
+    fn get_window_size(&self) -> u64;
+
+    fn get_user_tag(&self) -> u64;
+
+    fn get_global_time(&self) -> u64;
+
+    fn get_window_time(&self) -> u64;
+
+    fn get_global_work(&self) -> u64;
+
+    fn get_window_work(&self) -> u64;
+
+    fn get_global_perf(&self) -> f64;
+
+    fn get_window_perf(&self) -> f64;
+
+    fn get_instant_perf(&self) -> f64;
+
+    /// Get a view of accuracy data, if this variant tracks accuracy.
+    fn as_accuracy(&self) -> Option<&dyn HasAccuracy> {
+        None
+    }
+
+    /// Get a view of energy and power data, if this variant tracks power.
+    fn as_power(&self) -> Option<&dyn HasPower> {
+        None
+    }
+}
+
+/// A safe view of a heartbeat's state when its window buffer is complete.
+pub struct WindowSummary<'a, C: 'a, R: 'a> {
+    /// The heartbeat context.
+    pub context: &'a C,
+    /// The completed window's records, oldest first.
+    pub records: &'a [R],
+}
+
+/// Iterator over the valid records in a window buffer, oldest first.
+pub struct WindowRecords<'a, R: 'a> {
+    iter: Chain<slice::Iter<'a, R>, slice::Iter<'a, R>>,
+    len: usize,
+}
+
+impl<'a, R> WindowRecords<'a, R> {
+    /// `counter` is the number of heartbeats issued, `buffer_index` the next buffer slot to write.
+    pub(crate) fn new(records: &'a [R], counter: u64, buffer_index: u64) -> WindowRecords<'a, R> {
+        let (older, newer) = if counter < records.len() as u64 {
+            // buffer hasn't wrapped yet, remaining slots aren't valid
+            (&records[..counter as usize], &records[..0])
+        } else {
+            let (newer, older) = records.split_at(buffer_index as usize);
+            (older, newer)
+        };
+        WindowRecords { iter: older.iter().chain(newer.iter()), len: older.len() + newer.len() }
+    }
+}
+
+impl<'a, R> Iterator for WindowRecords<'a, R> {
+    type Item = &'a R;
+
+    fn next(&mut self) -> Option<&'a R> {
+        let next = self.iter.next();
+        if next.is_some() {
+            self.len -= 1;
+        }
+        next
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        (self.len, Some(self.len))
+    }
+}
+
+impl<'a, R> DoubleEndedIterator for WindowRecords<'a, R> {
+    fn next_back(&mut self) -> Option<&'a R> {
+        let next = self.iter.next_back();
+        if next.is_some() {
+            self.len -= 1;
+        }
+        next
+    }
+}
+
+impl<'a, R> ExactSizeIterator for WindowRecords<'a, R> {}
+
+/// Heartbeat variants that track accuracy.
+pub trait HasAccuracy: HeartbeatMonitor {
+    fn get_global_accuracy(&self) -> u64;
+
+    fn get_window_accuracy(&self) -> u64;
+
+    fn get_global_accuracy_rate(&self) -> f64;
+
+    fn get_window_accuracy_rate(&self) -> f64;
+
+    fn get_instant_accuracy_rate(&self) -> f64;
+}
+
+/// Heartbeat variants that track energy and power.
+pub trait HasPower: HeartbeatMonitor {
+    fn get_global_energy(&self) -> u64;
+
+    fn get_window_energy(&self) -> u64;
+
+    fn get_global_power(&self) -> f64;
+
+    fn get_window_power(&self) -> f64;
+
+    fn get_instant_power(&self) -> f64;
+}
+
+/// An in-memory log sink whose contents remain readable after it's moved into a heartbeat.
+#[cfg(test)]
+#[derive(Clone, Default)]
+pub(crate) struct TestLog(Arc<Mutex<Vec<u8>>>);
+
+#[cfg(test)]
+impl TestLog {
+    pub(crate) fn sink(&self) -> Option<LogSink> {
+        Some(Box::new(self.clone()))
+    }
+
+    pub(crate) fn contents(&self) -> String {
+        String::from_utf8(self.bytes()).unwrap()
+    }
+
+    pub(crate) fn bytes(&self) -> Vec<u8> {
+        self.0.lock().unwrap().clone()
+    }
+}
+
+#[cfg(test)]
+impl Write for TestLog {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.0.lock().unwrap().write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use hbs::Heartbeat;
+    use hbs_acc::HeartbeatAcc;
+    use hbs_pow::HeartbeatPow;
+    use hbs_acc_pow::HeartbeatAccPow;
+
+    fn check_monitor<T: HeartbeatMonitor>(hb: &mut T, has_acc: bool, has_pow: bool) {
+        assert_eq!(hb.get_window_size(), 5);
+        assert_eq!(hb.get_global_work(), 1);
+        assert_eq!(hb.get_global_time(), 1000);
+        assert!(hb.get_global_perf() > 0.0);
+        assert_eq!(hb.as_accuracy().is_some(), has_acc);
+        assert_eq!(hb.as_power().is_some(), has_pow);
+        if let Some(acc) = hb.as_accuracy() {
+            assert_eq!(acc.get_global_accuracy(), 1);
+        }
+        if let Some(pow) = hb.as_power() {
+            assert_eq!(pow.get_global_energy(), 1000);
+        }
+        hb.log_to_buffer_index().unwrap();
+    }
+
+    #[test]
+    fn test_window_records() {
+        let buf: [u64; 5] = [3, 4, 5, 1, 2];
+        let collect = |it: WindowRecords<u64>| it.cloned().collect::<Vec<_>>();
+        assert_eq!(WindowRecords::new(&buf, 0, 0).count(), 0);
+        assert_eq!(collect(WindowRecords::new(&buf, 3, 3)), vec![3, 4, 5]);
+        assert_eq!(collect(WindowRecords::new(&buf, 8, 3)), vec![1, 2, 3, 4, 5]);
+        assert_eq!(WindowRecords::new(&buf, 10, 0).rev().cloned().collect::<Vec<_>>(),
+                   vec![2, 1, 5, 4, 3]);
+        assert_eq!(WindowRecords::new(&buf, 8, 3).len(), 5);
+    }
+
+    #[test]
+    fn test_monitor() {
+        let mut hb = Heartbeat::new(5, None, None).unwrap();
+        hb.heartbeat(0, 1, 0, 1000);
+        check_monitor(&mut hb, false, false);
+
+        let mut hb = HeartbeatAcc::new(5, None, None).unwrap();
+        hb.heartbeat(0, 1, 0, 1000, 1);
+        check_monitor(&mut hb, true, false);
+
+        let mut hb = HeartbeatPow::new(5, None, None).unwrap();
+        hb.heartbeat(0, 1, 0, 1000, 0, 1000);
+        check_monitor(&mut hb, false, true);
+
+        let mut hb = HeartbeatAccPow::new(5, None, None).unwrap();
+        hb.heartbeat(0, 1, 0, 1000, 1, 0, 1000);
+        check_monitor(&mut hb, true, true);
+    }
+}
@@ -1,58 +1,137 @@
-use heartbeats_simple_sys::*;
+use backend::*;
 use std::mem;
 use std::io::{self, Write};
-use std::fs::File;
+use std::path::Path;
+use clock::{Clock, MonotonicClock};
+use energy::{self, EnergyReader};
+use error::HeartbeatError;
+use log::{BackgroundLog, Backpressure, LogFormat, LogOptions, LogRecord, LogVariant};
+use publish::Publisher;
+use hbs_common::{HeartbeatMonitor, HasPower, LogSink, WindowRecords, WindowSummary};
+use validate::ValidationPolicy;
 
-pub use heartbeats_simple_sys::heartbeat_pow_context as HeartbeatPowContext;
-pub use heartbeats_simple_sys::heartbeat_pow_record as HeartbeatPowRecord;
-pub use heartbeats_simple_sys::heartbeat_pow_window_complete as HeartbeatPowWindowComplete;
+pub use backend::heartbeat_pow_context as HeartbeatPowContext;
+pub use backend::heartbeat_pow_record as HeartbeatPowRecord;
+pub use backend::heartbeat_pow_window_complete as HeartbeatPowWindowComplete;
+
+/// A Rust closure called when the window buffer is complete.
+pub type HeartbeatPowWindowCallback =
+    Box<dyn FnMut(&WindowSummary<HeartbeatPowContext, HeartbeatPowRecord>) + Send>;
+
+/// A snapshot of all of a `HeartbeatPow`'s current statistics.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct HeartbeatPowStats {
+    pub window_size: u64,
+    pub user_tag: u64,
+    pub global_time: u64,
+    pub window_time: u64,
+    pub global_work: u64,
+    pub window_work: u64,
+    pub global_perf: f64,
+    pub window_perf: f64,
+    pub instant_perf: f64,
+    pub global_energy: u64,
+    pub window_energy: u64,
+    pub global_power: f64,
+    pub window_power: f64,
+    pub instant_power: f64,
+}
 
 /// Contains the Heartbeat and its window data buffer.
 pub struct HeartbeatPow {
     pub hb: HeartbeatPowContext,
     pub hbr: Vec<HeartbeatPowRecord>,
-    pub log: Option<File>,
+    pub log: Option<LogSink>,
+    hwc: Option<HeartbeatPowWindowCallback>,
+    log_index: u64,
+    log_err: Option<io::Error>,
+    log_format: LogFormat,
+    background: Option<BackgroundLog>,
+    publisher: Option<Publisher>,
+    clock: Box<dyn Clock + Send>,
+    begin_time: u64,
+    validation: ValidationPolicy,
+    energy_reader: Option<Box<dyn EnergyReader + Send>>,
+    begin_energy: u64,
+    energy_range: Option<u64>,
 }
 
 impl HeartbeatPow {
-    /// Allocate and initialize a new `Heartbeat`.
+    /// Allocate and initialize a new `HeartbeatPow`.
     pub fn new(window_size: usize,
                hwc_callback: HeartbeatPowWindowComplete,
-               mut log: Option<File>) -> Result<HeartbeatPow, &'static str> {
+               log: Option<LogSink>) -> Result<HeartbeatPow, HeartbeatError> {
+        HeartbeatPow::with_log_format(window_size, hwc_callback, log, LogFormat::FixedWidth)
+    }
+
+    /// Allocate and initialize a new `HeartbeatPow` that writes its log in `log_format`.
+    pub fn with_log_format(window_size: usize,
+                           hwc_callback: HeartbeatPowWindowComplete,
+                           log: Option<LogSink>,
+                           log_format: LogFormat) -> Result<HeartbeatPow, HeartbeatError> {
+        let options = LogOptions::new().format(log_format);
+        HeartbeatPow::with_log_options(window_size, hwc_callback, log, options)
+    }
+
+    /// Allocate and initialize a new `HeartbeatPow` with the given log options, e.g. to start
+    /// the log with metadata.
+    pub fn with_log_options(window_size: usize,
+                            hwc_callback: HeartbeatPowWindowComplete,
+                            mut log: Option<LogSink>,
+                            options: LogOptions) -> Result<HeartbeatPow, HeartbeatError> {
+        if window_size == 0 {
+            return Err(HeartbeatError::InvalidWindowSize(window_size));
+        }
         let mut hbr: Vec<HeartbeatPowRecord> = Vec::with_capacity(window_size);
         let hb: HeartbeatPowContext = unsafe {
             // must explicitly set size so we can read data later
             // (Rust isn't aware of native code modifying the buffer)
             hbr.set_len(window_size);
-            let mut hb = mem::uninitialized();
-            match heartbeat_pow_init(&mut hb,
+            let mut hb = mem::MaybeUninit::uninit();
+            match heartbeat_pow_init(hb.as_mut_ptr(),
                                      hbr.capacity() as u64,
                                      hbr.as_mut_ptr(),
                                      -1,
                                      hwc_callback) {
-                0 => hb,
-                _ => return Err("Failed to initialize heartbeat")
+                0 => hb.assume_init(),
+                rc => return Err(HeartbeatError::Init(rc))
             }
         };
+        let (log_format, clock, metadata) =
+            options.open(LogVariant::Pow, window_size, Box::new(MonotonicClock));
         // write header to log file if there is one
         if let Some(ref mut l) = log {
-            l.write_all(format!("{:6} {:6} \
-                         {:11} {:11} {:11} \
-                         {:15} {:15} {:20} {:20} \
-                         {:15} {:15} {:15} \
-                         {:15} {:15} {:15} {:15} \
-                         {:15} {:15} {}\n",
-                        "HB", "Tag",
-                        "Global_Work", "Window_Work", "Work",
-                        "Global_Time", "Window_Time", "Start_Time", "End_Time",
-                        "Global_Perf", "Window_Perf", "Instant_Perf",
-                        "Global_Energy", "Window_Energy", "Start_Energy", "End_Energy",
-                        "Global_Pwr", "Window_Pwr", "Instant_Pwr").as_bytes()).unwrap()
-        }
-        Ok(HeartbeatPow { hb: hb, hbr: hbr, log: log, })
+            match metadata {
+                Some(ref m) => log_format.write_header_with_metadata(LogVariant::Pow, m, l)?,
+                None => log_format.write_header(LogVariant::Pow, l)?,
+            }
+        }
+        let begin_time = clock.now();
+        Ok(HeartbeatPow {
+            hb,
+            hbr,
+            log,
+            hwc: None,
+            log_index: 0,
+            log_err: None,
+            log_format,
+            background: None,
+            publisher: None,
+            clock,
+            begin_time,
+            validation: ValidationPolicy::Reject,
+            energy_reader: None,
+            begin_energy: 0,
+            energy_range: None,
+        })
     }
 
     /// Issue a heartbeat
+    ///
+    /// If an energy range is configured (see `set_energy_range`), an `end_energy` less than
+    /// `start_energy` is treated as energy counter wraparound, and the corrected (unwrapped) end
+    /// energy is recorded.
     pub fn heartbeat(&mut self,
                      tag: u64,
                      work: u64,
@@ -60,39 +139,300 @@ impl HeartbeatPow {
                      end_time: u64,
                      start_energy: u64,
                      end_energy: u64) {
+        let end_energy = self.unwrap_energy(start_energy, end_energy);
         unsafe {
             heartbeat_pow(&mut self.hb, tag, work, start_time, end_time, start_energy, end_energy)
         }
+        if let Some(ref p) = self.publisher {
+            if let Some(r) = self.latest() {
+                p.publish(&LogRecord::from(r));
+            }
+        }
+        // the native code resets the buffer index when the window is complete
+        if self.hb.ws.buffer_index == 0 {
+            self.window_complete();
+        }
     }
 
-    fn write_log(r: &HeartbeatPowRecord, l: &mut File) -> io::Result<usize> {
-        l.write(format!("{:<6} {:<6} \
-                         {:<11} {:<11} {:<11} \
-                         {:<15} {:<15} {:<20} {:<20} \
-                         {:<15.6} {:<15.6} {:<15.6} \
-                         {:<15} {:<15} {:<15} {:<15} \
-                         {:<15.6} {:<15.6} {:<.6}\n",
-                        r.id, r.user_tag,
-                        r.wd.global, r.wd.window, r.work,
-                        r.td.global, r.td.window, r.start_time, r.end_time,
-                        r.perf.global, r.perf.window, r.perf.instant,
-                        r.ed.global, r.ed.window, r.start_energy, r.end_energy,
-                        r.pwr.global, r.pwr.window, r.pwr.instant).as_bytes())
-    }
-
-    /// Rust-only function that logs the buffer (up to buffer_index) to a file.
-    pub fn log_to_buffer_index(&mut self) -> io::Result<()> {
-        match self.log {
-            Some(ref mut l) => {
-                for i in 0..self.hb.ws.buffer_index {
-                    match HeartbeatPow::write_log(self.hbr.get(i as usize).unwrap(), l) {
-                        Ok(_) => (),
-                        Err(e) => return Err(e),
-                    }
-                }
-                l.flush()
+    /// Like `heartbeat`, but first validates that time and energy don't go backwards, handling
+    /// invalid input according to the validation policy (see `set_validation_policy`).
+    ///
+    /// Energy is checked after correcting for counter wraparound. Energy isn't compared to the
+    /// previous heartbeat when an energy range is configured, since the counter may wrap.
+    pub fn try_heartbeat(&mut self,
+                         tag: u64,
+                         work: u64,
+                         start_time: u64,
+                         mut end_time: u64,
+                         start_energy: u64,
+                         end_energy: u64) -> Result<(), HeartbeatError> {
+        let mut end_energy = self.unwrap_energy(start_energy, end_energy);
+        let prev = self.latest().map(|r| (r.end_time, r.end_energy));
+        let prev_end_energy = if self.energy_range.is_none() { prev.map(|p| p.1) } else { None };
+        self.validation.check_time(start_time, &mut end_time, prev.map(|p| p.0))?;
+        self.validation.check_energy(start_energy, &mut end_energy, prev_end_energy)?;
+        self.heartbeat(tag, work, start_time, end_time, start_energy, end_energy);
+        Ok(())
+    }
+
+    /// Set how `try_heartbeat` handles invalid input (`ValidationPolicy::Reject` by default).
+    pub fn set_validation_policy(&mut self, policy: ValidationPolicy) {
+        self.validation = policy;
+    }
+
+    /// Correct `end_energy` for counter wraparound, if an energy range is configured.
+    fn unwrap_energy(&self, start_energy: u64, end_energy: u64) -> u64 {
+        match self.energy_range {
+            Some(range) if end_energy < start_energy => {
+                let delta = energy::wrapped_energy_delta(start_energy, end_energy, range);
+                start_energy.saturating_add(delta)
             }
-            None => Ok(())
+            _ => end_energy,
+        }
+    }
+
+    /// Use `clock` to timestamp heartbeats issued by `begin`/`end` and `heartbeat_now`.
+    ///
+    /// Timestamps from different clocks aren't comparable, so set the clock before issuing any
+    /// heartbeats. This also restarts the current interval, as if `begin` was called.
+    pub fn set_clock<C: Clock + Send + 'static>(&mut self, clock: C) {
+        self.begin_time = clock.now();
+        self.clock = Box::new(clock);
+    }
+
+    /// Get the current time from the heartbeat's clock, in nanoseconds.
+    pub fn now(&self) -> u64 {
+        self.clock.now()
+    }
+
+    /// Start timing work to be reported by `end`.
+    pub fn begin(&mut self) {
+        self.begin_time = self.clock.now();
+    }
+
+    /// Issue a heartbeat for the work done since `begin` (or since the heartbeat was created).
+    pub fn end(&mut self, tag: u64, work: u64, start_energy: u64, end_energy: u64) {
+        let (start_time, end_time) = (self.begin_time, self.clock.now());
+        self.heartbeat(tag, work, start_time, end_time, start_energy, end_energy);
+    }
+
+    /// Issue a heartbeat for the work done since the previous heartbeat ended (or since `begin`
+    /// if there is no previous heartbeat), timestamped by the heartbeat's clock.
+    pub fn heartbeat_now(&mut self, tag: u64, work: u64, start_energy: u64, end_energy: u64) {
+        let start_time = self.latest().map_or(self.begin_time, |r| r.end_time);
+        let end_time = self.clock.now();
+        self.heartbeat(tag, work, start_time, end_time, start_energy, end_energy);
+    }
+
+    /// Start a scope that issues a heartbeat with `tag` when the returned guard is dropped.
+    ///
+    /// The heartbeat covers the time (and energy, starting from `start_energy`) from now until
+    /// the guard is dropped, so it's issued even on early returns.
+    pub fn scope(&mut self, tag: u64, start_energy: u64) -> HeartbeatPowScope<'_> {
+        let start_time = self.now();
+        HeartbeatPowScope {
+            hb: self,
+            tag,
+            work: 1,
+            start_time,
+            start_energy,
+            end_energy: start_energy,
+            sample_end: false,
+        }
+    }
+
+    /// Correct for energy counters that wrap back to 0 on reaching `range` (e.g., RAPL's
+    /// `max_energy_range_uj`), or `None` to disable correction.
+    ///
+    /// Setting an energy reader replaces this with the reader's maximum, if any.
+    pub fn set_energy_range(&mut self, range: Option<u64>) {
+        self.energy_range = range;
+    }
+
+    /// Use `reader` to sample energy for `begin_sampled`, `end_sampled`, `heartbeat_now_sampled`
+    /// and `scope_sampled`.
+    ///
+    /// Replaces the energy range (see `set_energy_range`) with the reader's maximum, clearing it
+    /// if the reader has none. Reads the current energy to start a new interval, as if
+    /// `begin_sampled` was called.
+    pub fn set_energy_reader<E: EnergyReader + Send + 'static>(&mut self, reader: E)
+        -> Result<(), HeartbeatError> {
+        self.energy_range = reader.max_energy();
+        self.energy_reader = Some(Box::new(reader));
+        self.begin_sampled()
+    }
+
+    /// Read the current energy from the heartbeat's energy reader, in microjoules.
+    pub fn read_energy(&mut self) -> Result<u64, HeartbeatError> {
+        match self.energy_reader {
+            Some(ref mut reader) => Ok(reader.read_energy()?),
+            None => Err(HeartbeatError::NoEnergyReader),
+        }
+    }
+
+    /// Like `begin`, but also samples the starting energy from the heartbeat's energy reader.
+    pub fn begin_sampled(&mut self) -> Result<(), HeartbeatError> {
+        self.begin_energy = self.read_energy()?;
+        self.begin();
+        Ok(())
+    }
+
+    /// Like `end`, but with energy sampled by the heartbeat's energy reader.
+    pub fn end_sampled(&mut self, tag: u64, work: u64) -> Result<(), HeartbeatError> {
+        let (start_energy, end_energy) = (self.begin_energy, self.read_energy()?);
+        self.end(tag, work, start_energy, end_energy);
+        Ok(())
+    }
+
+    /// Like `heartbeat_now`, but with energy sampled by the heartbeat's energy reader.
+    ///
+    /// The start energy is the previous heartbeat's end energy (or from `begin_sampled` if there
+    /// is no previous heartbeat).
+    pub fn heartbeat_now_sampled(&mut self, tag: u64, work: u64)
+        -> Result<(), HeartbeatError> {
+        let start_energy = self.latest().map_or(self.begin_energy, |r| r.end_energy);
+        let end_energy = self.read_energy()?;
+        self.heartbeat_now(tag, work, start_energy, end_energy);
+        Ok(())
+    }
+
+    /// Like `scope`, but with energy sampled by the heartbeat's energy reader at the start and
+    /// end of the scope.
+    pub fn scope_sampled(&mut self, tag: u64) -> Result<HeartbeatPowScope<'_>, HeartbeatError> {
+        let start_energy = self.read_energy()?;
+        let mut scope = self.scope(tag, start_energy);
+        scope.sample_end = true;
+        Ok(scope)
+    }
+
+    /// Register a closure to be called when the window buffer is complete.
+    ///
+    /// Unlike the `hwc_callback` passed to `new`, the closure may capture its own state.
+    pub fn set_window_callback<F>(&mut self, callback: F)
+        where F: FnMut(&WindowSummary<HeartbeatPowContext, HeartbeatPowRecord>) + Send + 'static {
+        self.hwc = Some(Box::new(callback));
+    }
+
+    fn window_complete(&mut self) {
+        // log records before they are overwritten, keeping errors for the next explicit flush
+        let window_size = self.hb.ws.window_size;
+        if let Err(e) = self.log_records(window_size) {
+            self.log_err.get_or_insert(e);
+        }
+        self.log_index = 0;
+        if let Some(ref mut cb) = self.hwc {
+            cb(&WindowSummary { context: &self.hb, records: &self.hbr });
+        }
+    }
+
+    /// Write any records not yet logged, up to (but not including) buffer index `end`.
+    fn log_records(&mut self, end: u64) -> io::Result<()> {
+        if let Some(ref bg) = self.background {
+            while self.log_index < end {
+                bg.send(LogRecord::from(&self.hbr[self.log_index as usize]));
+                self.log_index += 1;
+            }
+        } else if let Some(ref mut l) = self.log {
+            while self.log_index < end {
+                let r = LogRecord::from(&self.hbr[self.log_index as usize]);
+                self.log_format.write_record(&r, l)?;
+                self.log_index += 1;
+            }
+        }
+        Ok(())
+    }
+
+    /// Rust-only function that logs any records not yet written (up to buffer_index) to a file,
+    /// then flushes it.
+    ///
+    /// Records are also logged automatically when the window buffer is complete and when the
+    /// heartbeat is dropped, so each record is written exactly once.
+    /// Errors from automatic logging are reported by the next call to this function.
+    pub fn log_to_buffer_index(&mut self) -> Result<(), HeartbeatError> {
+        let buffer_index = self.hb.ws.buffer_index;
+        let res = self.log_records(buffer_index).and_then(|_| match self.log {
+            Some(ref mut l) => l.flush(),
+            None => self.background.as_ref().map_or(Ok(()), BackgroundLog::flush),
+        });
+        match self.log_err.take() {
+            Some(e) => Err(e.into()),
+            None => res.map_err(HeartbeatError::from),
+        }
+    }
+
+    /// Move the log to a background thread, which formats and writes records handed to it
+    /// through a queue of up to `capacity` records.
+    ///
+    /// Logging then never blocks on I/O, unless `backpressure` is `Backpressure::Block` and the
+    /// queue is full. `log_to_buffer_index` only requests a flush, and reports errors from the
+    /// background thread. The queue is drained when the heartbeat is dropped.
+    /// Does nothing if there is no log.
+    pub fn set_background_log(&mut self,
+                              capacity: usize,
+                              backpressure: Backpressure) -> Result<(), HeartbeatError> {
+        if let Some(log) = self.log.take() {
+            let bg = BackgroundLog::new(log, self.log_format, capacity, backpressure)?;
+            self.background = Some(bg);
+        }
+        Ok(())
+    }
+
+    /// The number of records the background log dropped because its queue was full.
+    pub fn dropped_log_records(&self) -> u64 {
+        self.background.as_ref().map_or(0, BackgroundLog::dropped)
+    }
+
+    /// Publish the current statistics and window to a memory-mapped file at `path` after every
+    /// heartbeat, so other local processes can observe them with `PublishedReader`.
+    ///
+    /// Records already in the window are published immediately.
+    pub fn publish_to<P: AsRef<Path>>(&mut self, path: P) -> Result<(), HeartbeatError> {
+        let window_size = self.hb.ws.window_size as usize;
+        let publisher = Publisher::create(path, LogVariant::Pow, window_size)?;
+        for r in self.records() {
+            publisher.publish(&LogRecord::from(r));
+        }
+        self.publisher = Some(publisher);
+        Ok(())
+    }
+
+    /// Iterate over the valid records in the window buffer, oldest first.
+    pub fn records(&self) -> WindowRecords<'_, HeartbeatPowRecord> {
+        WindowRecords::new(&self.hbr, self.hb.counter, self.hb.ws.buffer_index)
+    }
+
+    /// The most recent record, if any heartbeats have been issued.
+    pub fn latest(&self) -> Option<&HeartbeatPowRecord> {
+        self.records().next_back()
+    }
+
+    /// The number of valid records in the window buffer.
+    pub fn len(&self) -> usize {
+        self.records().len()
+    }
+
+    /// Whether no heartbeats have been issued yet.
+    pub fn is_empty(&self) -> bool {
+        self.hb.counter == 0
+    }
+
+    /// Get a snapshot of all the current statistics.
+    pub fn stats(&self) -> HeartbeatPowStats {
+        HeartbeatPowStats {
+            window_size: self.get_window_size(),
+            user_tag: self.get_user_tag(),
+            global_time: self.get_global_time(),
+            window_time: self.get_window_time(),
+            global_work: self.get_global_work(),
+            window_work: self.get_window_work(),
+            global_perf: self.get_global_perf(),
+            window_perf: self.get_window_perf(),
+            instant_perf: self.get_instant_perf(),
+            global_energy: self.get_global_energy(),
+            window_energy: self.get_window_energy(),
+            global_power: self.get_global_power(),
+            window_power: self.get_window_power(),
+            instant_power: self.get_instant_power(),
         }
     }
 
@@ -181,10 +521,131 @@ impl HeartbeatPow {
     }
 }
 
+/// Issues a heartbeat when dropped, covering the time and energy since the scope was started.
+///
+/// Created by `HeartbeatPow::scope`.
+pub struct HeartbeatPowScope<'a> {
+    hb: &'a mut HeartbeatPow,
+    tag: u64,
+    work: u64,
+    start_time: u64,
+    start_energy: u64,
+    end_energy: u64,
+    sample_end: bool,
+}
+
+impl<'a> HeartbeatPowScope<'a> {
+    /// Set the work completed in this scope (1 by default).
+    pub fn set_work(&mut self, work: u64) {
+        self.work = work;
+    }
+
+    /// Set the energy reading at the end of this scope (the start energy by default).
+    ///
+    /// Has no effect on scopes started by `scope_sampled`.
+    pub fn set_end_energy(&mut self, end_energy: u64) {
+        self.end_energy = end_energy;
+    }
+}
+
+impl<'a> Drop for HeartbeatPowScope<'a> {
+    fn drop(&mut self) {
+        let end_time = self.hb.now();
+        if self.sample_end {
+            // errors can't be reported here, so report no energy consumed instead
+            self.end_energy = self.hb.read_energy().unwrap_or(self.start_energy);
+        }
+        self.hb.heartbeat(self.tag, self.work, self.start_time, end_time,
+                          self.start_energy, self.end_energy);
+    }
+}
+
+impl Drop for HeartbeatPow {
+    fn drop(&mut self) {
+        // errors can't be reported here; call log_to_buffer_index first to check for them
+        let _ = self.log_to_buffer_index();
+    }
+}
+
+// The context's raw pointer refers to the window buffer, which is owned and moved along with it.
+unsafe impl Send for HeartbeatPow {}
+
+impl HeartbeatMonitor for HeartbeatPow {
+    fn log_to_buffer_index(&mut self) -> Result<(), HeartbeatError> {
+        HeartbeatPow::log_to_buffer_index(self)
+    }
+
+    fn get_window_size(&self) -> u64 {
+        HeartbeatPow::get_window_size(self)
+    }
+
+    fn get_user_tag(&self) -> u64 {
+        HeartbeatPow::get_user_tag(self)
+    }
+
+    fn get_global_time(&self) -> u64 {
+        HeartbeatPow::get_global_time(self)
+    }
+
+    fn get_window_time(&self) -> u64 {
+        HeartbeatPow::get_window_time(self)
+    }
+
+    fn get_global_work(&self) -> u64 {
+        HeartbeatPow::get_global_work(self)
+    }
+
+    fn get_window_work(&self) -> u64 {
+        HeartbeatPow::get_window_work(self)
+    }
+
+    fn get_global_perf(&self) -> f64 {
+        HeartbeatPow::get_global_perf(self)
+    }
+
+    fn get_window_perf(&self) -> f64 {
+        HeartbeatPow::get_window_perf(self)
+    }
+
+    fn get_instant_perf(&self) -> f64 {
+        HeartbeatPow::get_instant_perf(self)
+    }
+
+    fn as_power(&self) -> Option<&dyn HasPower> {
+        Some(self)
+    }
+}
+
+impl HasPower for HeartbeatPow {
+    fn get_global_energy(&self) -> u64 {
+        HeartbeatPow::get_global_energy(self)
+    }
+
+    fn get_window_energy(&self) -> u64 {
+        HeartbeatPow::get_window_energy(self)
+    }
+
+    fn get_global_power(&self) -> f64 {
+        HeartbeatPow::get_global_power(self)
+    }
+
+    fn get_window_power(&self) -> f64 {
+        HeartbeatPow::get_window_power(self)
+    }
+
+    fn get_instant_power(&self) -> f64 {
+        HeartbeatPow::get_instant_power(self)
+    }
+}
+
 #[cfg(test)]
 mod test {
     use super::*;
+    use clock::TestClock;
+    use energy::TestEnergy;
+    use hbs_common::TestLog;
     use std::fs::File;
+    use std::sync::{Arc, Mutex};
 
     #[test]
     fn test_simple() {
@@ -207,7 +668,7 @@ mod test {
     #[test]
     fn test_callback() {
         static mut RECEIVED_CB: bool = false;
-        extern fn callback(_hb: *const HeartbeatPowContext) {
+        extern "C" fn callback(_hb: *const HeartbeatPowContext) {
             unsafe {
                 RECEIVED_CB = true;
             }
@@ -220,10 +681,232 @@ mod test {
         }
     }
 
+    #[test]
+    fn test_window_callback() {
+        let ids = Arc::new(Mutex::new(Vec::new()));
+        let cb_ids = ids.clone();
+        let mut hb = HeartbeatPow::new(2, None, None).unwrap();
+        hb.set_window_callback(move |w| {
+            cb_ids.lock().unwrap().extend(w.records.iter().map(|r| r.id));
+        });
+        for tag in 0..5 {
+            hb.heartbeat(tag, 1, tag * 1000, (tag + 1) * 1000, 0, 0);
+        }
+        assert_eq!(*ids.lock().unwrap(), vec![0, 1, 2, 3]);
+    }
+
     #[test]
     fn test_file() {
-        let mut hb = HeartbeatPow::new(5, None, Some(File::create("foo.log").unwrap())).unwrap();
+        let mut hb = HeartbeatPow::new(5, None, Some(Box::new(File::create("foo.log").unwrap()))).unwrap();
         hb.heartbeat(0, 1, 0, 1000, 0, 0);
         hb.log_to_buffer_index().unwrap();
     }
+
+    #[test]
+    fn test_log_records_once() {
+        let log = TestLog::default();
+        {
+            let mut hb = HeartbeatPow::new(2, None, log.sink()).unwrap();
+            for tag in 0..5 {
+                hb.heartbeat(tag, 1, tag * 1000, (tag + 1) * 1000, 0, 0);
+                if tag == 2 {
+                    hb.log_to_buffer_index().unwrap();
+                    hb.log_to_buffer_index().unwrap();
+                }
+            }
+        }
+        let ids: Vec<u64> = log.contents().lines().skip(1)
+            .map(|l| l.split_whitespace().next().unwrap().parse().unwrap())
+            .collect();
+        assert_eq!(ids, vec![0, 1, 2, 3, 4]);
+    }
+
+    #[test]
+    fn test_log_format() {
+        let log = TestLog::default();
+        {
+            let mut hb = HeartbeatPow::with_log_format(2, None, log.sink(), LogFormat::Csv).unwrap();
+            for tag in 0..3 {
+                hb.heartbeat(tag, 1, tag * 1000, (tag + 1) * 1000, 0, 0);
+            }
+        }
+        let contents = log.contents();
+        let lines: Vec<&str> = contents.lines().collect();
+        assert_eq!(lines.len(), 4);
+        assert_eq!(lines[0], LogVariant::Pow.columns().join(","));
+        assert!(lines[3].starts_with("2,2,3,2,1,3000,2000,2000,3000,"));
+    }
+
+    #[test]
+    fn test_background_log() {
+        let log = TestLog::default();
+        {
+            let mut hb = HeartbeatPow::new(4, None, log.sink()).unwrap();
+            hb.set_background_log(2, Backpressure::Block).unwrap();
+            for tag in 0..10 {
+                hb.heartbeat(tag, 1, tag * 1000, (tag + 1) * 1000, 0, 0);
+            }
+            hb.log_to_buffer_index().unwrap();
+            assert_eq!(hb.dropped_log_records(), 0);
+        }
+        let ids: Vec<u64> = log.contents().lines().skip(1)
+            .map(|l| l.split_whitespace().next().unwrap().parse().unwrap())
+            .collect();
+        assert_eq!(ids, (0..10).collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn test_invalid_window_size() {
+        match HeartbeatPow::new(0, None, None) {
+            Err(HeartbeatError::InvalidWindowSize(0)) => (),
+            _ => panic!("Expected InvalidWindowSize"),
+        }
+    }
+
+    #[test]
+    fn test_records() {
+        let mut hb = HeartbeatPow::new(3, None, None).unwrap();
+        assert!(hb.is_empty());
+        assert!(hb.latest().is_none());
+        for tag in 0..2 {
+            hb.heartbeat(tag, 1, tag * 1000, (tag + 1) * 1000, 0, 0);
+        }
+        assert_eq!(hb.records().map(|r| r.id).collect::<Vec<_>>(), vec![0, 1]);
+        for tag in 2..5 {
+            hb.heartbeat(tag, 1, tag * 1000, (tag + 1) * 1000, 0, 0);
+        }
+        assert_eq!(hb.records().map(|r| r.user_tag).collect::<Vec<_>>(), vec![2, 3, 4]);
+        assert_eq!(hb.latest().unwrap().id, 4);
+        assert_eq!(hb.len(), 3);
+    }
+
+    #[test]
+    fn test_stats() {
+        let mut hb = HeartbeatPow::new(5, None, None).unwrap();
+        hb.heartbeat(7, 1, 0, 1000, 0, 1000);
+        let stats = hb.stats();
+        assert_eq!(stats.user_tag, 7);
+        assert_eq!(stats.window_size, 5);
+        assert_eq!(stats.global_work, hb.get_global_work());
+        assert_eq!(stats.instant_perf, hb.get_instant_perf());
+        assert_eq!(stats.window_power, hb.get_window_power());
+        assert_eq!(stats, hb.stats().clone());
+    }
+
+    #[test]
+    fn test_clock() {
+        let clock = TestClock::default();
+        let mut hb = HeartbeatPow::new(5, None, None).unwrap();
+        hb.set_clock(clock.clone());
+        clock.advance(1000);
+        hb.heartbeat_now(0, 1, 0, 0);
+        clock.advance(500);
+        hb.begin();
+        clock.advance(2000);
+        hb.end(1, 1, 0, 0);
+        clock.advance(100);
+        hb.heartbeat_now(2, 1, 0, 0);
+        let times: Vec<_> = hb.records().map(|r| (r.start_time, r.end_time)).collect();
+        assert_eq!(times, vec![(0, 1000), (1500, 3500), (3500, 3600)]);
+    }
+
+    #[test]
+    fn test_scope() {
+        fn work(hb: &mut HeartbeatPow, clock: &TestClock, fail: bool) -> Result<(), ()> {
+            let mut scope = hb.scope(0, 1000);
+            clock.advance(1000);
+            scope.set_work(3);
+            scope.set_end_energy(5000);
+            if fail {
+                return Err(());
+            }
+            Ok(())
+        }
+
+        let clock = TestClock::default();
+        let mut hb = HeartbeatPow::new(5, None, None).unwrap();
+        hb.set_clock(clock.clone());
+        work(&mut hb, &clock, false).unwrap();
+        work(&mut hb, &clock, true).unwrap_err();
+        assert_eq!(hb.len(), 2);
+        let r = hb.latest().unwrap();
+        assert_eq!((r.start_time, r.end_time), (1000, 2000));
+        assert_eq!(r.work, 3);
+        assert_eq!((r.start_energy, r.end_energy), (1000, 5000));
+    }
+
+    #[test]
+    fn test_energy_reader() {
+        let (clock, energy) = (TestClock::default(), TestEnergy::default());
+        let mut hb = HeartbeatPow::new(5, None, None).unwrap();
+        match hb.heartbeat_now_sampled(0, 1) {
+            Err(HeartbeatError::NoEnergyReader) => (),
+            _ => panic!("Expected NoEnergyReader"),
+        }
+        hb.set_clock(clock.clone());
+        energy.advance(100);
+        hb.set_energy_reader(energy.clone()).unwrap();
+        clock.advance(1000);
+        energy.advance(2000);
+        hb.heartbeat_now_sampled(0, 1).unwrap();
+        hb.begin_sampled().unwrap();
+        clock.advance(1000);
+        energy.advance(500);
+        hb.end_sampled(1, 1).unwrap();
+        {
+            let _scope = hb.scope_sampled(2).unwrap();
+            clock.advance(1000);
+            energy.advance(1000);
+        }
+        let energies: Vec<_> = hb.records().map(|r| (r.start_energy, r.end_energy)).collect();
+        assert_eq!(energies, vec![(100, 2100), (2100, 2600), (2600, 3600)]);
+        assert_eq!(hb.get_instant_power(), 1000.0);
+    }
+
+    #[test]
+    fn test_energy_wraparound() {
+        let mut hb = HeartbeatPow::new(5, None, None).unwrap();
+        hb.set_energy_range(Some(1000));
+        hb.heartbeat(0, 1, 0, 1000000000, 900, 100);
+        assert_eq!(hb.latest().unwrap().end_energy, 1100);
+        assert_eq!(hb.get_instant_power(), 0.0002);
+        // a range near the limit of the counter's type doesn't overflow
+        let mut hb = HeartbeatPow::new(5, None, None).unwrap();
+        hb.set_energy_range(Some(u64::MAX));
+        hb.heartbeat(0, 1, 0, 1000000000, u64::MAX - 100, 100);
+        assert_eq!(hb.latest().unwrap().end_energy, u64::MAX);
+
+        // drive a simulated wrapping counter through sampled heartbeats
+        let (clock, energy) = (TestClock::default(), TestEnergy::wrapping(1000));
+        let mut hb = HeartbeatPow::new(5, None, None).unwrap();
+        hb.set_clock(clock.clone());
+        energy.advance(700);
+        hb.set_energy_reader(energy.clone()).unwrap();
+        for tag in 0..6 {
+            clock.advance(1000000000);
+            energy.advance(200);
+            hb.heartbeat_now_sampled(tag, 1).unwrap();
+            assert_eq!(hb.get_instant_power(), 0.0002);
+        }
+        assert_eq!(hb.get_global_energy(), 1200);
+        // a reader without a maximum doesn't inherit the previous reader's range
+        hb.set_energy_reader(TestEnergy::default()).unwrap();
+        hb.heartbeat(6, 1, 0, 1000000000, 900, 100);
+        assert_eq!(hb.latest().unwrap().end_energy, 100);
+    }
+
+    #[test]
+    fn test_try_heartbeat() {
+        let mut hb = HeartbeatPow::new(5, None, None).unwrap();
+        hb.try_heartbeat(0, 1, 0, 1000, 0, 100).unwrap();
+        assert!(hb.try_heartbeat(1, 1, 2000, 1500, 100, 200).is_err());
+        assert!(hb.try_heartbeat(1, 1, 500, 900, 100, 200).is_err());
+        assert!(hb.try_heartbeat(1, 1, 1000, 2000, 200, 150).is_err());
+        assert!(hb.try_heartbeat(1, 1, 1000, 2000, 50, 80).is_err());
+        assert_eq!(hb.len(), 1);
+        hb.set_validation_policy(ValidationPolicy::Clamp);
+        hb.try_heartbeat(1, 1, 2000, 1500, 200, 150).unwrap();
+        assert_eq!(hb.latest().unwrap().end_time, 2000);
+        assert_eq!(hb.latest().unwrap().end_energy, 200);
+    }
 }
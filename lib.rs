@@ -1,12 +1,40 @@
 extern crate libc;
+#[cfg(feature = "heartbeats-simple-sys")]
 extern crate heartbeats_simple_sys;
+#[cfg(feature = "serde")]
+#[macro_use]
+extern crate serde;
+#[cfg(feature = "gzip")]
+extern crate flate2;
 
+#[cfg(not(any(feature = "heartbeats-simple-sys", feature = "pure-rust")))]
+compile_error!("Either the \"heartbeats-simple-sys\" or the \"pure-rust\" feature must be enabled");
+
+mod backend;
+mod clock;
+mod energy;
+mod error;
+mod hbs_common;
 mod hbs;
 mod hbs_acc;
 mod hbs_pow;
 mod hbs_acc_pow;
+pub mod log;
+#[cfg(any(feature = "pure-rust", test))]
+#[cfg_attr(not(feature = "pure-rust"), allow(dead_code))]
+mod pure;
+mod publish;
+mod shared;
+mod validate;
 
+pub use clock::*;
+pub use energy::*;
+pub use error::*;
+pub use hbs_common::*;
 pub use hbs::*;
 pub use hbs_acc::*;
 pub use hbs_pow::*;
 pub use hbs_acc_pow::*;
+pub use publish::*;
+pub use shared::*;
+pub use validate::*;
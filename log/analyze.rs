@@ -0,0 +1,375 @@
+//! Summarize heartbeat logs, as done by the `hbs-analyze` tool, and draw sparklines for
+//! `hbs-top`.
+
+use std::io::{self, Write};
+use std::iter::FromIterator;
+use super::format::{json_string, Value};
+use super::{LogRecord, LogVariant};
+
+const ONE_BILLION: f64 = 1000000000.0;
+const ONE_MILLION: f64 = 1000000.0;
+
+/// The percentiles included in summary output.
+pub const PERCENTILES: [f64; 3] = [50.0, 90.0, 99.0];
+
+/// The distribution of a per-heartbeat rate.
+///
+/// Non-finite values (e.g., the rates of zero-length heartbeats) are ignored.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Distribution {
+    sorted: Vec<f64>,
+    sum: f64,
+}
+
+impl FromIterator<f64> for Distribution {
+    fn from_iter<I: IntoIterator<Item = f64>>(values: I) -> Distribution {
+        let mut sorted: Vec<f64> = values.into_iter().filter(|v| v.is_finite()).collect();
+        sorted.sort_by(|a, b| a.partial_cmp(b).unwrap());
+        let sum = sorted.iter().sum();
+        Distribution { sorted, sum }
+    }
+}
+
+impl Distribution {
+    /// The number of values.
+    pub fn count(&self) -> usize {
+        self.sorted.len()
+    }
+
+    pub fn mean(&self) -> Option<f64> {
+        if self.sorted.is_empty() {
+            None
+        } else {
+            Some(self.sum / self.sorted.len() as f64)
+        }
+    }
+
+    pub fn min(&self) -> Option<f64> {
+        self.sorted.first().cloned()
+    }
+
+    pub fn max(&self) -> Option<f64> {
+        self.sorted.last().cloned()
+    }
+
+    /// The `p`th percentile (0 to 100), interpolating linearly between values.
+    pub fn percentile(&self, p: f64) -> Option<f64> {
+        let last = self.sorted.len().checked_sub(1)?;
+        let rank = p.clamp(0.0, 100.0) / 100.0 * last as f64;
+        let (lo, hi) = (rank.floor() as usize, rank.ceil() as usize);
+        let (a, b) = (self.sorted[lo], self.sorted[hi]);
+        Some(a + (b - a) * (rank - lo as f64))
+    }
+}
+
+/// Totals and distributions for a heartbeat log.
+///
+/// Time is in nanoseconds and energy in microjoules, as in the log; rates are per second and
+/// power is in watts.
+#[derive(Debug, Clone, PartialEq)]
+pub struct LogSummary {
+    pub variant: LogVariant,
+    /// The number of records.
+    pub records: u64,
+    /// The total work.
+    pub work: u64,
+    /// The total time spent in heartbeats.
+    pub time: u64,
+    /// The distribution of instant performance.
+    pub perf: Distribution,
+    /// The total accuracy, for variants with accuracy.
+    pub accuracy: Option<u64>,
+    /// The distribution of instant accuracy rate, for variants with accuracy.
+    pub accuracy_rate: Option<Distribution>,
+    /// The total energy, for variants with power.
+    pub energy: Option<u64>,
+    /// The distribution of instant power, for variants with power.
+    pub power: Option<Distribution>,
+}
+
+impl LogSummary {
+    /// Summarize the records of a log written by `variant`, stopping at the first error.
+    pub fn from_records<I, E>(variant: LogVariant, records: I) -> Result<LogSummary, E>
+        where I: IntoIterator<Item = Result<LogRecord, E>>
+    {
+        let (mut count, mut work, mut time, mut accuracy, mut energy) = (0, 0, 0, 0, 0u64);
+        let (mut perf, mut acc_rate, mut power) = (Vec::new(), Vec::new(), Vec::new());
+        for r in records {
+            let r = r?;
+            count += 1;
+            work += r.work;
+            time += r.end_time.wrapping_sub(r.start_time);
+            perf.push(r.instant_perf);
+            if let Some(a) = r.accuracy {
+                accuracy += a.accuracy;
+                acc_rate.push(a.instant_accuracy_rate);
+            }
+            if let Some(p) = r.power {
+                energy += p.end_energy.wrapping_sub(p.start_energy);
+                power.push(p.instant_power);
+            }
+        }
+        let has_acc = variant.has_accuracy();
+        let has_pow = variant.has_power();
+        Ok(LogSummary {
+            variant,
+            records: count,
+            work,
+            time,
+            perf: perf.into_iter().collect(),
+            accuracy: if has_acc { Some(accuracy) } else { None },
+            accuracy_rate: if has_acc { Some(acc_rate.into_iter().collect()) } else { None },
+            energy: if has_pow { Some(energy) } else { None },
+            power: if has_pow { Some(power.into_iter().collect()) } else { None },
+        })
+    }
+
+    fn seconds(&self) -> f64 {
+        self.time as f64 / ONE_BILLION
+    }
+
+    /// Work per second over the total time.
+    pub fn overall_perf(&self) -> f64 {
+        self.work as f64 / self.seconds()
+    }
+
+    /// Accuracy per second over the total time, for variants with accuracy.
+    pub fn overall_accuracy_rate(&self) -> Option<f64> {
+        self.accuracy.map(|a| a as f64 / self.seconds())
+    }
+
+    /// Average power over the total time, for variants with power.
+    pub fn overall_power(&self) -> Option<f64> {
+        self.energy.map(|e| e as f64 / self.seconds() / ONE_MILLION)
+    }
+
+    /// Energy per unit of work, for variants with power.
+    pub fn energy_per_work(&self) -> Option<f64> {
+        self.energy.map(|e| e as f64 / self.work as f64)
+    }
+
+    /// The summary's values as named fields, `None` where the variant doesn't have them.
+    fn fields(&self) -> Vec<(String, Option<Value>)> {
+        let mut fields = vec![
+            ("records".to_string(), Some(Value::Int(self.records))),
+            ("work".to_string(), Some(Value::Int(self.work))),
+            ("time_ns".to_string(), Some(Value::Int(self.time))),
+            ("perf".to_string(), Some(Value::Float(self.overall_perf()))),
+        ];
+        distribution_fields("instant_perf", Some(&self.perf), &mut fields);
+        fields.push(("accuracy".to_string(), self.accuracy.map(Value::Int)));
+        fields.push(("accuracy_rate".to_string(),
+                     self.overall_accuracy_rate().map(Value::Float)));
+        distribution_fields("instant_accuracy_rate", self.accuracy_rate.as_ref(), &mut fields);
+        fields.push(("energy_uj".to_string(), self.energy.map(Value::Int)));
+        fields.push(("energy_per_work".to_string(), self.energy_per_work().map(Value::Float)));
+        fields.push(("power_w".to_string(), self.overall_power().map(Value::Float)));
+        distribution_fields("instant_power", self.power.as_ref(), &mut fields);
+        fields
+    }
+}
+
+fn distribution_fields(name: &str,
+                       d: Option<&Distribution>,
+                       fields: &mut Vec<(String, Option<Value>)>) {
+    let mut push = |stat: &str, v: Option<f64>| {
+        fields.push((format!("{}_{}", name, stat), v.map(Value::Float)));
+    };
+    push("mean", d.and_then(Distribution::mean));
+    push("min", d.and_then(Distribution::min));
+    push("max", d.and_then(Distribution::max));
+    for &p in PERCENTILES.iter() {
+        push(&format!("p{}", p), d.and_then(|d| d.percentile(p)));
+    }
+}
+
+/// The layout of `LogSummary` output.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum SummaryFormat {
+    /// Indented `name value` lines under the log name, omitting fields the variant doesn't have.
+    #[default]
+    Text,
+    /// Comma-separated values with a header row, leaving fields the variant doesn't have empty.
+    Csv,
+    /// One JSON object per log, with `null` for fields the variant doesn't have.
+    Json,
+}
+
+impl SummaryFormat {
+    /// Write the header, if the format has one.
+    pub fn write_header(self, w: &mut dyn Write) -> io::Result<()> {
+        if self != SummaryFormat::Csv {
+            return Ok(());
+        }
+        let summary = LogSummary::from_records::<_, ()>(LogVariant::AccPow, vec![]).unwrap();
+        let mut names = vec!["log".to_string(), "variant".to_string()];
+        names.extend(summary.fields().into_iter().map(|f| f.0));
+        writeln!(w, "{}", names.join(","))
+    }
+
+    /// Write the summary of the log named `name`.
+    pub fn write_summary(self,
+                         name: &str,
+                         summary: &LogSummary,
+                         w: &mut dyn Write) -> io::Result<()> {
+        let fields = summary.fields();
+        let text = match self {
+            SummaryFormat::Text => {
+                let variant = summary.variant.name();
+                let mut text = format!("{}\n  {:<28} {}\n", name, "variant", variant);
+                for (k, v) in fields {
+                    match v {
+                        Some(Value::Int(i)) => text.push_str(&format!("  {:<28} {}\n", k, i)),
+                        Some(Value::Float(f)) => {
+                            text.push_str(&format!("  {:<28} {:.6}\n", k, f))
+                        }
+                        None => (),
+                    }
+                }
+                text
+            }
+            SummaryFormat::Csv => {
+                let mut values = vec![csv_string(name), summary.variant.name().to_string()];
+                values.extend(fields.into_iter().map(|(_, v)| match v {
+                    Some(Value::Int(i)) => i.to_string(),
+                    Some(Value::Float(f)) => f.to_string(),
+                    None => String::new(),
+                }));
+                values.join(",") + "\n"
+            }
+            SummaryFormat::Json => {
+                let mut values = vec![format!("\"log\":{}", json_string(name)),
+                                      format!("\"variant\":{}",
+                                              json_string(summary.variant.name()))];
+                values.extend(fields.into_iter().map(|(k, v)| match v {
+                    Some(Value::Int(i)) => format!("\"{}\":{}", k, i),
+                    Some(Value::Float(f)) if f.is_finite() => format!("\"{}\":{:?}", k, f),
+                    _ => format!("\"{}\":null", k),
+                }));
+                format!("{{{}}}\n", values.join(","))
+            }
+        };
+        w.write_all(text.as_bytes())
+    }
+}
+
+/// Characters used by `sparkline`, from lowest to highest.
+const SPARKS: [char; 8] = ['▁', '▂', '▃', '▄', '▅', '▆', '▇', '█'];
+
+/// Draw `values` as a line of block characters scaled between their minimum and maximum, with
+/// spaces for non-finite values.
+pub fn sparkline(values: &[f64]) -> String {
+    let finite = values.iter().cloned().filter(|v| v.is_finite());
+    let min = finite.clone().fold(f64::INFINITY, f64::min);
+    let max = finite.fold(f64::NEG_INFINITY, f64::max);
+    let top = (SPARKS.len() - 1) as f64;
+    values.iter().map(|&v| {
+        if !v.is_finite() {
+            ' '
+        } else if max > min {
+            SPARKS[((v - min) / (max - min) * top).round() as usize]
+        } else {
+            SPARKS[SPARKS.len() / 2]
+        }
+    }).collect()
+}
+
+/// Quote a CSV field if necessary.
+fn csv_string(s: &str) -> String {
+    if s.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", s.replace('"', "\"\""))
+    } else {
+        s.to_string()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use log::{LogAccuracy, LogPower};
+
+    fn records() -> Vec<Result<LogRecord, ()>> {
+        (0..4u64).map(|i| Ok(LogRecord {
+            id: i,
+            work: i + 1,
+            start_time: i * 1000000000,
+            end_time: (i + 1) * 1000000000,
+            instant_perf: (i + 1) as f64,
+            power: Some(LogPower {
+                start_energy: i * 1000000,
+                end_energy: (i + 2) * 1000000,
+                instant_power: 2.0,
+                ..LogPower::default()
+            }),
+            ..LogRecord::default()
+        })).collect()
+    }
+
+    #[test]
+    fn test_distribution() {
+        let d: Distribution = vec![4.0, f64::NAN, 1.0, 3.0, 2.0, f64::INFINITY].into_iter()
+            .collect();
+        assert_eq!(d.count(), 4);
+        assert_eq!((d.mean(), d.min(), d.max()), (Some(2.5), Some(1.0), Some(4.0)));
+        assert_eq!(d.percentile(0.0), Some(1.0));
+        assert_eq!(d.percentile(50.0), Some(2.5));
+        assert_eq!(d.percentile(100.0), Some(4.0));
+        let empty: Distribution = vec![].into_iter().collect();
+        assert_eq!((empty.mean(), empty.percentile(50.0)), (None, None));
+    }
+
+    #[test]
+    fn test_summary() {
+        let s = LogSummary::from_records(LogVariant::Pow, records()).unwrap();
+        assert_eq!((s.records, s.work, s.time), (4, 10, 4000000000));
+        assert_eq!(s.overall_perf(), 2.5);
+        assert_eq!(s.perf.mean(), Some(2.5));
+        assert_eq!(s.energy, Some(8000000));
+        assert_eq!(s.energy_per_work(), Some(800000.0));
+        assert_eq!(s.overall_power(), Some(2.0));
+        assert_eq!(s.accuracy, None);
+        assert!(s.accuracy_rate.is_none());
+
+        let acc = Ok(LogRecord {
+            accuracy: Some(LogAccuracy { accuracy: 3, ..LogAccuracy::default() }),
+            ..LogRecord::default()
+        });
+        let s = LogSummary::from_records(LogVariant::Acc, vec![acc, Err("oops")]);
+        assert_eq!(s, Err("oops"));
+    }
+
+    #[test]
+    fn test_sparkline() {
+        assert_eq!(sparkline(&[0.0, 1.0, 2.0, 3.0, 7.0, f64::NAN]), "▁▂▃▄█ ");
+        assert_eq!(sparkline(&[5.0, 5.0]), "▅▅");
+        assert_eq!(sparkline(&[]), "");
+    }
+
+    #[test]
+    fn test_formats() {
+        let s = LogSummary::from_records(LogVariant::Pow, records()).unwrap();
+        let mut text = Vec::new();
+        SummaryFormat::Text.write_summary("a.log", &s, &mut text).unwrap();
+        let text = String::from_utf8(text).unwrap();
+        assert!(text.starts_with("a.log\n  variant"));
+        assert!(text.contains("  energy_per_work              800000.000000\n"));
+        assert!(!text.contains("accuracy"));
+
+        let mut csv = Vec::new();
+        SummaryFormat::Csv.write_header(&mut csv).unwrap();
+        SummaryFormat::Csv.write_summary("a,b.log", &s, &mut csv).unwrap();
+        let csv = String::from_utf8(csv).unwrap();
+        let lines: Vec<&str> = csv.lines().collect();
+        assert!(lines[0].starts_with("log,variant,records,work,time_ns,perf,instant_perf_mean,"));
+        assert!(lines[1].starts_with("\"a,b.log\",HeartbeatPow,4,10,4000000000,2.5,2.5,"));
+        assert_eq!(lines[0].split(',').count(), lines[1].split(',').count() - 1);
+
+        let mut json = Vec::new();
+        SummaryFormat::Json.write_header(&mut json).unwrap();
+        SummaryFormat::Json.write_summary("a.log", &s, &mut json).unwrap();
+        let json = String::from_utf8(json).unwrap();
+        assert!(json.starts_with("{\"log\":\"a.log\",\"variant\":\"HeartbeatPow\",\"records\":4,"));
+        assert!(json.contains("\"accuracy\":null,"));
+        assert!(json.contains("\"instant_perf_p50\":2.5,"));
+    }
+}
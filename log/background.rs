@@ -0,0 +1,371 @@
+use std::collections::VecDeque;
+use std::io;
+use std::mem;
+use std::sync::{Arc, Condvar, Mutex, MutexGuard};
+use std::thread::{self, JoinHandle};
+use hbs_common::LogSink;
+use super::{LogFormat, LogRecord};
+
+/// What `BackgroundLog::send` does when the queue is full.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Backpressure {
+    /// Wait for the writer thread to make room, so no records are lost.
+    #[default]
+    Block,
+    /// Drop the oldest queued record to make room, counting it as dropped.
+    DropOldest,
+    /// Drop the new record, counting it as dropped.
+    CountDrops,
+}
+
+struct State {
+    records: VecDeque<LogRecord>,
+    dropped: u64,
+    flush: bool,
+    shutdown: bool,
+    /// The writer thread has exited, e.g. because the sink panicked.
+    stopped: bool,
+    error: Option<io::Error>,
+}
+
+struct Queue {
+    state: Mutex<State>,
+    capacity: usize,
+    backpressure: Backpressure,
+    not_empty: Condvar,
+    not_full: Condvar,
+}
+
+impl Queue {
+    fn lock(&self) -> MutexGuard<'_, State> {
+        self.state.lock().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+/// Writes log records on a dedicated thread, fed by a bounded queue.
+///
+/// Dropping the log drains the queue and flushes the sink before returning.
+pub struct BackgroundLog {
+    queue: Arc<Queue>,
+    writer: Option<JoinHandle<()>>,
+}
+
+impl BackgroundLog {
+    /// Start a thread that writes records to `sink` in `format`, queueing up to `capacity`
+    /// records (at least 1).
+    ///
+    /// Any header must already have been written to `sink`.
+    pub fn new(sink: LogSink,
+               format: LogFormat,
+               capacity: usize,
+               backpressure: Backpressure) -> io::Result<BackgroundLog> {
+        let capacity = capacity.max(1);
+        let queue = Arc::new(Queue {
+            state: Mutex::new(State {
+                records: VecDeque::with_capacity(capacity),
+                dropped: 0,
+                flush: false,
+                shutdown: false,
+                stopped: false,
+                error: None,
+            }),
+            capacity,
+            backpressure,
+            not_empty: Condvar::new(),
+            not_full: Condvar::new(),
+        });
+        let writer_queue = queue.clone();
+        let writer = thread::Builder::new()
+            .name("heartbeat-log".to_string())
+            .spawn(move || write_records(&writer_queue, sink, format))?;
+        Ok(BackgroundLog { queue, writer: Some(writer) })
+    }
+
+    /// Queue a record to be written, handling a full queue according to the backpressure policy.
+    ///
+    /// If the writer thread has stopped (e.g. because the sink panicked), the record is dropped.
+    pub fn send(&self, record: LogRecord) {
+        let mut state = self.queue.lock();
+        while state.records.len() >= self.queue.capacity && !state.stopped {
+            match self.queue.backpressure {
+                Backpressure::Block => {
+                    state = self.queue.not_full.wait(state).unwrap_or_else(|e| e.into_inner());
+                }
+                Backpressure::DropOldest => {
+                    state.records.pop_front();
+                    state.dropped += 1;
+                }
+                Backpressure::CountDrops => {
+                    state.dropped += 1;
+                    return;
+                }
+            }
+        }
+        if state.stopped {
+            state.dropped += 1;
+            return;
+        }
+        state.records.push_back(record);
+        self.queue.not_empty.notify_one();
+    }
+
+    /// Ask the writer thread to flush the sink after writing the queued records, without
+    /// waiting for it.
+    ///
+    /// Returns the first error the writer thread has encountered since the last call, if any.
+    pub fn flush(&self) -> io::Result<()> {
+        let mut state = self.queue.lock();
+        state.flush = true;
+        self.queue.not_empty.notify_one();
+        state.error.take().map_or(Ok(()), Err)
+    }
+
+    /// The number of records dropped because the queue was full, because a write error
+    /// stopped the writer thread before it wrote them, or because the writer thread stopped.
+    pub fn dropped(&self) -> u64 {
+        self.queue.lock().dropped
+    }
+}
+
+impl Drop for BackgroundLog {
+    fn drop(&mut self) {
+        self.queue.lock().shutdown = true;
+        self.queue.not_empty.notify_one();
+        if let Some(writer) = self.writer.take() {
+            // a panic in the sink has nowhere to go
+            let _ = writer.join();
+        }
+    }
+}
+
+/// Marks the writer thread stopped however it exits, so senders don't wait for it forever.
+struct Stopped<'a>(&'a Queue);
+
+impl<'a> Drop for Stopped<'a> {
+    fn drop(&mut self) {
+        let mut state = self.0.lock();
+        state.stopped = true;
+        state.dropped += state.records.len() as u64;
+        state.records.clear();
+        if thread::panicking() {
+            state.error.get_or_insert_with(|| io::Error::other("log writer thread panicked"));
+        }
+        self.0.not_full.notify_all();
+    }
+}
+
+/// The writer thread: write queued records in batches until shut down.
+fn write_records(queue: &Queue, mut sink: LogSink, format: LogFormat) {
+    let _stopped = Stopped(queue);
+    let mut batch = Vec::with_capacity(queue.capacity);
+    loop {
+        let (flush, shutdown) = {
+            let mut state = queue.lock();
+            while state.records.is_empty() && !state.flush && !state.shutdown {
+                state = queue.not_empty.wait(state).unwrap_or_else(|e| e.into_inner());
+            }
+            batch.extend(state.records.drain(..));
+            queue.not_full.notify_all();
+            (mem::replace(&mut state.flush, false), state.shutdown)
+        };
+        let mut res = Ok(());
+        let mut lost = 0;
+        for (i, r) in batch.iter().enumerate() {
+            if let Err(e) = format.write_record(r, &mut sink) {
+                // the rest of the batch would likely fail too, and may not follow a partial record
+                res = Err(e);
+                lost = batch.len() - i;
+                break;
+            }
+        }
+        batch.clear();
+        if res.is_ok() && (flush || shutdown) {
+            res = sink.flush();
+        }
+        if let Err(e) = res {
+            let mut state = queue.lock();
+            state.dropped += lost as u64;
+            state.error.get_or_insert(e);
+        }
+        if shutdown {
+            return;
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use hbs_common::TestLog;
+    use std::io::Write;
+    use std::sync::atomic::{AtomicBool, Ordering};
+    use std::sync::mpsc::{self, Sender};
+    use std::time::Duration;
+
+    /// A sink that reports each write, then waits for the gate to open.
+    struct GateSink {
+        started: Sender<()>,
+        gate: Arc<Mutex<()>>,
+        out: TestLog,
+    }
+
+    impl Write for GateSink {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            let _ = self.started.send(());
+            let _open = self.gate.lock().unwrap();
+            self.out.write(buf)
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    fn record(id: u64) -> LogRecord {
+        LogRecord { id, ..LogRecord::default() }
+    }
+
+    /// Send record 0, wait for the writer to block on it, then send records `1..n` and open the
+    /// gate, returning the IDs written and the number of records dropped.
+    fn run(backpressure: Backpressure, n: u64) -> (Vec<u64>, u64) {
+        let out = TestLog::default();
+        let gate = Arc::new(Mutex::new(()));
+        let closed = gate.lock().unwrap();
+        let (started, writing) = mpsc::channel();
+        let sink = GateSink { started, gate: gate.clone(), out: out.clone() };
+        let log = Arc::new(BackgroundLog::new(Box::new(sink), LogFormat::Csv, 2, backpressure)
+            .unwrap());
+        log.send(record(0));
+        writing.recv().unwrap();
+        let sent = Arc::new(AtomicBool::new(false));
+        let sender = {
+            let (log, sent) = (log.clone(), sent.clone());
+            thread::spawn(move || {
+                for id in 1..n {
+                    log.send(record(id));
+                }
+                sent.store(true, Ordering::SeqCst);
+            })
+        };
+        if backpressure == Backpressure::Block {
+            thread::sleep(Duration::from_millis(50));
+            assert!(!sent.load(Ordering::SeqCst));
+        } else {
+            while !sent.load(Ordering::SeqCst) {
+                thread::yield_now();
+            }
+        }
+        drop(closed);
+        sender.join().unwrap();
+        let dropped = log.dropped();
+        drop(Arc::try_unwrap(log).ok().unwrap());
+        let ids = out.contents().lines()
+            .map(|l| l.split(',').next().unwrap().parse().unwrap())
+            .collect();
+        (ids, dropped)
+    }
+
+    #[test]
+    fn test_block() {
+        assert_eq!(run(Backpressure::Block, 5), (vec![0, 1, 2, 3, 4], 0));
+    }
+
+    #[test]
+    fn test_drop_oldest() {
+        assert_eq!(run(Backpressure::DropOldest, 5), (vec![0, 3, 4], 2));
+    }
+
+    #[test]
+    fn test_count_drops() {
+        assert_eq!(run(Backpressure::CountDrops, 5), (vec![0, 1, 2], 2));
+    }
+
+    #[test]
+    fn test_flush_error() {
+        struct FullDisk;
+        impl Write for FullDisk {
+            fn write(&mut self, _buf: &[u8]) -> io::Result<usize> {
+                Err(io::Error::other("disk full"))
+            }
+            fn flush(&mut self) -> io::Result<()> {
+                Ok(())
+            }
+        }
+
+        let log = BackgroundLog::new(Box::new(FullDisk), LogFormat::Csv, 4, Backpressure::Block)
+            .unwrap();
+        log.send(record(0));
+        while log.flush().is_ok() {
+            thread::yield_now();
+        }
+        assert!(log.flush().is_ok());
+        assert_eq!(log.dropped(), 1);
+    }
+
+    #[test]
+    fn test_write_error_drops_batch() {
+        /// Writes the first record once the gate opens, then fails.
+        struct FailingSink {
+            started: Sender<()>,
+            gate: Arc<Mutex<()>>,
+            writes: usize,
+        }
+        impl Write for FailingSink {
+            fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+                self.writes += 1;
+                if self.writes > 1 {
+                    return Err(io::Error::other("disk full"));
+                }
+                let _ = self.started.send(());
+                let _open = self.gate.lock().unwrap();
+                Ok(buf.len())
+            }
+            fn flush(&mut self) -> io::Result<()> {
+                Ok(())
+            }
+        }
+
+        let gate = Arc::new(Mutex::new(()));
+        let closed = gate.lock().unwrap();
+        let (started, writing) = mpsc::channel();
+        let sink = FailingSink { started, gate: gate.clone(), writes: 0 };
+        let log = BackgroundLog::new(Box::new(sink), LogFormat::Csv, 4, Backpressure::Block)
+            .unwrap();
+        log.send(record(0));
+        writing.recv().unwrap();
+        // queued behind record 0, so written as one batch
+        for id in 1..4 {
+            log.send(record(id));
+        }
+        drop(closed);
+        while log.flush().is_ok() {
+            thread::yield_now();
+        }
+        assert_eq!(log.dropped(), 3);
+    }
+
+    #[test]
+    fn test_panicking_sink() {
+        struct PanickingSink;
+        impl Write for PanickingSink {
+            fn write(&mut self, _buf: &[u8]) -> io::Result<usize> {
+                panic!("sink panicked");
+            }
+            fn flush(&mut self) -> io::Result<()> {
+                Ok(())
+            }
+        }
+
+        let log = BackgroundLog::new(Box::new(PanickingSink), LogFormat::Csv, 1,
+                                     Backpressure::Block).unwrap();
+        // would block forever without a writer to make room
+        for id in 0..10 {
+            log.send(record(id));
+        }
+        while log.flush().is_ok() {
+            thread::yield_now();
+        }
+        // the record being written when the sink panicked isn't counted
+        assert_eq!(log.dropped(), 9);
+    }
+}
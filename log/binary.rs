@@ -0,0 +1,365 @@
+//! A compact binary log format.
+//!
+//! A log starts with an 8-byte header: the magic bytes `HBSB`, the format version as a
+//! little-endian `u16`, the `LogVariant` as a `u8` (0 = `Heartbeat`, 1 = `Acc`, 2 = `Pow`,
+//! 3 = `AccPow`), and a flags byte.
+//! If flag bit 0 is set, the header is followed by a metadata block: a little-endian `u32`
+//! length, followed by UTF-8 `key: value` lines.
+//! Each record is a little-endian `u32` payload length, followed by the record's values in log
+//! column order as little-endian `u64`s (rates as `f64` bits).
+//! Readers skip payload bytes beyond the values they know, so later versions may append values.
+//! Readers reject metadata blocks over 64 KiB and payloads over four times the largest record.
+
+use std::io::{self, Read, Write};
+use super::format::{record_from_values, visit_values, Value};
+use super::read::ReadError;
+use super::{LogFormat, LogMetadata, LogRecord, LogVariant};
+
+/// Identifies binary heartbeat logs.
+pub const MAGIC: [u8; 4] = *b"HBSB";
+/// The binary format version written by this crate.
+pub const VERSION: u16 = 1;
+
+/// Header flag indicating a metadata block follows.
+const FLAG_METADATA: u8 = 1;
+
+/// The largest record payload written (by `HeartbeatAccPow`).
+const MAX_PAYLOAD: usize = 25 * 8;
+/// The largest record payload read, leaving room for values appended by later versions.
+const MAX_READ_PAYLOAD: usize = 4 * MAX_PAYLOAD;
+/// The largest metadata block read.
+const MAX_METADATA: usize = 64 * 1024;
+
+pub(crate) fn variant_code(variant: LogVariant) -> u8 {
+    match variant {
+        LogVariant::Heartbeat => 0,
+        LogVariant::Acc => 1,
+        LogVariant::Pow => 2,
+        LogVariant::AccPow => 3,
+    }
+}
+
+pub(crate) fn variant_from_code(code: u8) -> Option<LogVariant> {
+    LogVariant::ALL.iter().cloned().find(|&v| variant_code(v) == code)
+}
+
+/// Write the header for a binary log of `variant`.
+pub fn write_header(variant: LogVariant, w: &mut dyn Write) -> io::Result<()> {
+    write_header_with_metadata(variant, None, w)
+}
+
+/// Write the header for a binary log of `variant`, followed by a metadata block if given.
+pub fn write_header_with_metadata(variant: LogVariant,
+                                  metadata: Option<&LogMetadata>,
+                                  w: &mut dyn Write) -> io::Result<()> {
+    let mut header = [0u8; 8];
+    header[..4].copy_from_slice(&MAGIC);
+    header[4..6].copy_from_slice(&VERSION.to_le_bytes());
+    header[6] = variant_code(variant);
+    if let Some(metadata) = metadata {
+        header[7] = FLAG_METADATA;
+        let text = metadata.to_text("");
+        let mut buf = header.to_vec();
+        buf.extend_from_slice(&(text.len() as u32).to_le_bytes());
+        buf.extend_from_slice(text.as_bytes());
+        w.write_all(&buf)
+    } else {
+        w.write_all(&header)
+    }
+}
+
+/// Write a record, without allocating.
+pub fn write_record(r: &LogRecord, w: &mut dyn Write) -> io::Result<()> {
+    let mut buf = [0u8; 4 + MAX_PAYLOAD];
+    let mut len = 4;
+    visit_values(r, |v| {
+        let bytes = match v {
+            Value::Int(i) => i.to_le_bytes(),
+            Value::Float(f) => f.to_bits().to_le_bytes(),
+        };
+        buf[len..len + 8].copy_from_slice(&bytes);
+        len += 8;
+    });
+    buf[..4].copy_from_slice(&((len - 4) as u32).to_le_bytes());
+    w.write_all(&buf[..len])
+}
+
+/// Iterates over the records of a binary log, reading it incrementally.
+///
+/// `ReadError::Malformed` errors report the 1-based record number as the line.
+/// Records can't be found after an error, so iteration ends after the first one.
+pub struct BinaryLogReader<R> {
+    reader: R,
+    variant: LogVariant,
+    version: u16,
+    metadata: LogMetadata,
+    record: usize,
+    buf: Vec<u8>,
+    failed: bool,
+}
+
+impl<R: Read> BinaryLogReader<R> {
+    /// Read the header from `reader`.
+    pub fn new(mut reader: R) -> Result<BinaryLogReader<R>, ReadError> {
+        let mut header = [0u8; 8];
+        reader.read_exact(&mut header).map_err(|e| match e.kind() {
+            io::ErrorKind::UnexpectedEof => ReadError::MissingHeader,
+            _ => e.into(),
+        })?;
+        if header[..4] != MAGIC {
+            return Err(ReadError::UnknownHeader(format!("magic bytes {:?}", &header[..4])));
+        }
+        let version = u16::from_le_bytes([header[4], header[5]]);
+        if version == 0 || version > VERSION {
+            return Err(ReadError::UnknownHeader(format!("unsupported version {}", version)));
+        }
+        let variant = variant_from_code(header[6]).ok_or_else(|| {
+            ReadError::UnknownHeader(format!("unknown variant {}", header[6]))
+        })?;
+        let mut metadata = LogMetadata::default();
+        if header[7] & FLAG_METADATA != 0 {
+            let mut len = [0u8; 4];
+            reader.read_exact(&mut len)?;
+            let len = u32::from_le_bytes(len) as usize;
+            if len > MAX_METADATA {
+                return Err(ReadError::UnknownHeader(format!("metadata of {} bytes exceeds {}",
+                                                            len, MAX_METADATA)));
+            }
+            let mut text = vec![0u8; len];
+            reader.read_exact(&mut text)?;
+            for line in String::from_utf8_lossy(&text).lines() {
+                metadata.parse_entry(line);
+            }
+        }
+        Ok(BinaryLogReader {
+            reader,
+            variant,
+            version,
+            metadata,
+            record: 0,
+            buf: Vec::new(),
+            failed: false,
+        })
+    }
+
+    /// The heartbeat variant that wrote the log.
+    pub fn variant(&self) -> LogVariant {
+        self.variant
+    }
+
+    /// The log's metadata, which is empty if it has none.
+    pub fn metadata(&self) -> &LogMetadata {
+        &self.metadata
+    }
+
+    /// The format version of the log.
+    pub fn version(&self) -> u16 {
+        self.version
+    }
+
+    /// Read the next record payload into the buffer, returning false at the end of the log.
+    fn read_payload(&mut self) -> Result<bool, ReadError> {
+        let mut len = [0u8; 4];
+        // distinguish a clean end of log from a truncated length
+        let mut read = 0;
+        while read < len.len() {
+            match self.reader.read(&mut len[read..]) {
+                Ok(0) if read == 0 => return Ok(false),
+                Ok(0) => return Err(self.malformed("truncated record length".to_string())),
+                Ok(n) => read += n,
+                Err(ref e) if e.kind() == io::ErrorKind::Interrupted => (),
+                Err(e) => return Err(e.into()),
+            }
+        }
+        let len = u32::from_le_bytes(len) as usize;
+        let expected = self.variant.columns().len() * 8;
+        if len < expected {
+            return Err(self.malformed(format!("expected at least {} bytes, found {}",
+                                              expected, len)));
+        }
+        if len > MAX_READ_PAYLOAD {
+            return Err(self.malformed(format!("expected at most {} bytes, found {}",
+                                              MAX_READ_PAYLOAD, len)));
+        }
+        self.buf.resize(len, 0);
+        match self.reader.read_exact(&mut self.buf) {
+            Ok(()) => Ok(true),
+            Err(ref e) if e.kind() == io::ErrorKind::UnexpectedEof => {
+                Err(self.malformed(format!("truncated record, expected {} bytes", len)))
+            }
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    fn malformed(&self, msg: String) -> ReadError {
+        ReadError::Malformed { line: self.record, msg }
+    }
+
+    fn parse(&self) -> LogRecord {
+        let mut values = self.buf.chunks_exact(8).map(|b| {
+            let mut value = [0u8; 8];
+            value.copy_from_slice(b);
+            u64::from_le_bytes(value)
+        });
+        // read_payload checked there are enough values
+        record_from_values(self.variant, || values.next().unwrap())
+    }
+}
+
+impl<R: Read> Iterator for BinaryLogReader<R> {
+    type Item = Result<LogRecord, ReadError>;
+
+    fn next(&mut self) -> Option<Result<LogRecord, ReadError>> {
+        if self.failed {
+            return None;
+        }
+        self.record += 1;
+        match self.read_payload() {
+            Ok(true) => Some(Ok(self.parse())),
+            Ok(false) => None,
+            Err(e) => {
+                self.failed = true;
+                Some(Err(e))
+            }
+        }
+    }
+}
+
+/// Convert a binary log to a text `format`, returning the number of records converted.
+pub fn convert<R: Read, W: Write>(input: R,
+                                  output: &mut W,
+                                  format: LogFormat) -> Result<u64, ReadError> {
+    let reader = BinaryLogReader::new(input)?;
+    if reader.metadata().is_empty() {
+        format.write_header(reader.variant(), output)?;
+    } else {
+        format.write_header_with_metadata(reader.variant(), reader.metadata(), output)?;
+    }
+    let mut count = 0;
+    for r in reader {
+        format.write_record(&r?, output)?;
+        count += 1;
+    }
+    Ok(count)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use hbs_acc_pow::HeartbeatAccPow;
+    use hbs_common::TestLog;
+    use log::LogOptions;
+    use log::read::LogReader;
+
+    fn binary_log() -> (TestLog, Vec<LogRecord>) {
+        let log = TestLog::default();
+        let mut hb = HeartbeatAccPow::with_log_format(4, None, log.sink(), LogFormat::Binary)
+            .unwrap();
+        for tag in 0..3 {
+            hb.heartbeat(tag, 1, tag * 1000, (tag + 1) * 1000, 2, tag * 500, (tag + 1) * 500);
+        }
+        hb.log_to_buffer_index().unwrap();
+        let records = hb.records().map(LogRecord::from).collect();
+        (log, records)
+    }
+
+    #[test]
+    fn test_round_trip() {
+        let (log, records) = binary_log();
+        let bytes = log.bytes();
+        assert_eq!(bytes.len(), 8 + 3 * (4 + 25 * 8));
+        let reader = BinaryLogReader::new(&bytes[..]).unwrap();
+        assert_eq!(reader.variant(), LogVariant::AccPow);
+        assert_eq!(reader.version(), VERSION);
+        let read: Vec<LogRecord> = reader.collect::<Result<_, _>>().unwrap();
+        assert_eq!(read, records);
+    }
+
+    #[test]
+    fn test_convert() {
+        let (log, records) = binary_log();
+        let mut text = Vec::new();
+        assert_eq!(convert(&log.bytes()[..], &mut text, LogFormat::FixedWidth).unwrap(), 3);
+        let read: Vec<LogRecord> = LogReader::new(&text[..]).unwrap()
+            .collect::<Result<_, _>>()
+            .unwrap();
+        assert_eq!(read.len(), 3);
+        assert_eq!(read[2].end_time, records[2].end_time);
+        assert_eq!(read[2].power.unwrap().end_energy, 1500);
+    }
+
+    #[test]
+    fn test_metadata() {
+        let log = TestLog::default();
+        let options = LogOptions::new().format(LogFormat::Binary).metadata(true);
+        let mut hb = HeartbeatAccPow::with_log_options(4, None, log.sink(), options).unwrap();
+        hb.heartbeat(0, 1, 0, 1000, 2, 0, 500);
+        hb.log_to_buffer_index().unwrap();
+        let bytes = log.bytes();
+        let reader = BinaryLogReader::new(&bytes[..]).unwrap();
+        assert_eq!(reader.metadata().variant(), Some(LogVariant::AccPow));
+        assert_eq!(reader.metadata().clock(), Some("CLOCK_MONOTONIC"));
+        assert_eq!(reader.count(), 1);
+        // conversion keeps the metadata
+        let mut text = Vec::new();
+        convert(&bytes[..], &mut text, LogFormat::FixedWidth).unwrap();
+        let reader = LogReader::new(&text[..]).unwrap();
+        assert_eq!(reader.metadata().window_size(), Some(4));
+    }
+
+    #[test]
+    fn test_extra_payload() {
+        // a later version may append values, which are skipped
+        let mut bytes = Vec::new();
+        write_header(LogVariant::Heartbeat, &mut bytes).unwrap();
+        bytes.extend_from_slice(&(13u32 * 8).to_le_bytes());
+        bytes.extend((0..13u64).flat_map(|v| v.to_le_bytes().to_vec()));
+        let read: Vec<LogRecord> = BinaryLogReader::new(&bytes[..]).unwrap()
+            .collect::<Result<_, _>>()
+            .unwrap();
+        assert_eq!((read[0].id, read[0].end_time), (0, 8));
+    }
+
+    #[test]
+    fn test_malformed() {
+        match BinaryLogReader::new(&b"HB     Tag"[..]) {
+            Err(ReadError::UnknownHeader(_)) => (),
+            _ => panic!("Expected UnknownHeader"),
+        }
+        match BinaryLogReader::new(&b"HBSB\x09\x00\x00\x00"[..]) {
+            Err(ReadError::UnknownHeader(msg)) => assert_eq!(msg, "unsupported version 9"),
+            _ => panic!("Expected UnknownHeader"),
+        }
+        let (log, _) = binary_log();
+        let bytes = log.bytes();
+        let truncated = &bytes[..bytes.len() - 10];
+        let results: Vec<_> = BinaryLogReader::new(truncated).unwrap().collect();
+        assert_eq!(results.len(), 3);
+        match results[2] {
+            Err(ReadError::Malformed { line: 3, .. }) => (),
+            _ => panic!("Expected Malformed"),
+        }
+    }
+
+    #[test]
+    fn test_corrupted_length() {
+        let mut bytes = Vec::new();
+        write_header(LogVariant::Heartbeat, &mut bytes).unwrap();
+        bytes.extend_from_slice(&u32::MAX.to_le_bytes());
+        let results: Vec<_> = BinaryLogReader::new(&bytes[..]).unwrap().collect();
+        match results[..] {
+            [Err(ReadError::Malformed { line: 1, ref msg })] => {
+                assert_eq!(*msg, format!("expected at most 800 bytes, found {}", u32::MAX));
+            }
+            _ => panic!("Expected Malformed"),
+        }
+        let mut bytes = b"HBSB\x01\x00\x00\x01".to_vec();
+        bytes.extend_from_slice(&u32::MAX.to_le_bytes());
+        match BinaryLogReader::new(&bytes[..]) {
+            Err(ReadError::UnknownHeader(msg)) => {
+                assert_eq!(msg, format!("metadata of {} bytes exceeds 65536", u32::MAX));
+            }
+            _ => panic!("Expected UnknownHeader"),
+        }
+    }
+}
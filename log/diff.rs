@@ -0,0 +1,578 @@
+//! Compare a baseline heartbeat log against a new one, as done by the `hbs-diff` tool.
+
+use std::collections::{HashMap, VecDeque};
+use std::fmt;
+use std::io::{self, Write};
+use super::format::json_string;
+use super::LogRecord;
+
+const ONE_BILLION: f64 = 1000000000.0;
+const ONE_MILLION: f64 = 1000000.0;
+
+/// How records from the two logs are paired.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Alignment {
+    /// Pair records by position in the log.
+    #[default]
+    Index,
+    /// Pair records with the same tag, in order of occurrence.
+    Tag,
+}
+
+impl Alignment {
+    pub fn name(self) -> &'static str {
+        match self {
+            Alignment::Index => "index",
+            Alignment::Tag => "tag",
+        }
+    }
+}
+
+/// The change in a rate between the baseline and candidate logs.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct MetricDiff {
+    /// The rate over the baseline's aligned records.
+    pub baseline: f64,
+    /// The rate over the candidate's aligned records.
+    pub candidate: f64,
+    /// The two-sided p-value of Welch's t-test on the instant rates of the aligned records,
+    /// or `None` if either log has fewer than two finite instant rates.
+    pub p_value: Option<f64>,
+}
+
+impl MetricDiff {
+    fn new(baseline: f64, candidate: f64, instant: (&[f64], &[f64])) -> MetricDiff {
+        MetricDiff { baseline, candidate, p_value: welch_t_test(instant.0, instant.1) }
+    }
+
+    pub fn delta(&self) -> f64 {
+        self.candidate - self.baseline
+    }
+
+    /// The change relative to the baseline, e.g. -0.05 for a 5% drop.
+    pub fn relative(&self) -> f64 {
+        self.delta() / self.baseline
+    }
+}
+
+/// Totals over aligned records, and their instant rates.
+#[derive(Default)]
+struct Totals {
+    work: u64,
+    time: u64,
+    accuracy: Option<u64>,
+    energy: Option<u64>,
+    perf: Vec<f64>,
+    accuracy_rate: Vec<f64>,
+    power: Vec<f64>,
+}
+
+impl Totals {
+    fn new<'a, I: Iterator<Item = &'a LogRecord>>(records: I) -> Totals {
+        let mut t = Totals { accuracy: Some(0), energy: Some(0), ..Totals::default() };
+        for r in records {
+            t.work += r.work;
+            t.time += r.end_time.wrapping_sub(r.start_time);
+            t.perf.push(r.instant_perf);
+            t.accuracy = match (t.accuracy, r.accuracy) {
+                (Some(sum), Some(a)) => {
+                    t.accuracy_rate.push(a.instant_accuracy_rate);
+                    Some(sum + a.accuracy)
+                }
+                _ => None,
+            };
+            t.energy = match (t.energy, r.power) {
+                (Some(sum), Some(p)) => {
+                    t.power.push(p.instant_power);
+                    Some(sum + p.end_energy.wrapping_sub(p.start_energy))
+                }
+                _ => None,
+            };
+        }
+        t
+    }
+
+    fn seconds(&self) -> f64 {
+        self.time as f64 / ONE_BILLION
+    }
+}
+
+/// The differences between a baseline log and a candidate log.
+///
+/// Rates are computed over the aligned records only: performance is work per second, power is
+/// in watts, and accuracy rate is accuracy per second.
+#[derive(Debug, Clone, PartialEq)]
+pub struct LogDiff {
+    pub alignment: Alignment,
+    /// The number of aligned record pairs.
+    pub aligned: usize,
+    /// The number of baseline records without a candidate record.
+    pub unmatched_baseline: usize,
+    /// The number of candidate records without a baseline record.
+    pub unmatched_candidate: usize,
+    pub perf: MetricDiff,
+    /// The change in power, if both logs have power data.
+    pub power: Option<MetricDiff>,
+    /// The change in accuracy rate, if both logs have accuracy data.
+    pub accuracy_rate: Option<MetricDiff>,
+}
+
+impl LogDiff {
+    /// Align and compare the records of two logs.
+    pub fn new(baseline: &[LogRecord], candidate: &[LogRecord], alignment: Alignment) -> LogDiff {
+        let pairs: Vec<(&LogRecord, &LogRecord)> = match alignment {
+            Alignment::Index => baseline.iter().zip(candidate.iter()).collect(),
+            Alignment::Tag => {
+                let mut by_tag: HashMap<u64, VecDeque<&LogRecord>> = HashMap::new();
+                for r in candidate {
+                    by_tag.entry(r.user_tag).or_default().push_back(r);
+                }
+                baseline.iter()
+                    .filter_map(|b| by_tag.get_mut(&b.user_tag)
+                                .and_then(VecDeque::pop_front)
+                                .map(|c| (b, c)))
+                    .collect()
+            }
+        };
+        let b = Totals::new(pairs.iter().map(|p| p.0));
+        let c = Totals::new(pairs.iter().map(|p| p.1));
+        let (bs, cs) = (b.seconds(), c.seconds());
+        let perf = MetricDiff::new(b.work as f64 / bs, c.work as f64 / cs, (&b.perf, &c.perf));
+        let power = match (b.energy, c.energy) {
+            (Some(be), Some(ce)) => Some(MetricDiff::new(be as f64 / bs / ONE_MILLION,
+                                                         ce as f64 / cs / ONE_MILLION,
+                                                         (&b.power, &c.power))),
+            _ => None,
+        };
+        let accuracy_rate = match (b.accuracy, c.accuracy) {
+            (Some(ba), Some(ca)) => Some(MetricDiff::new(ba as f64 / bs,
+                                                         ca as f64 / cs,
+                                                         (&b.accuracy_rate, &c.accuracy_rate))),
+            _ => None,
+        };
+        LogDiff {
+            alignment,
+            aligned: pairs.len(),
+            unmatched_baseline: baseline.len() - pairs.len(),
+            unmatched_candidate: candidate.len() - pairs.len(),
+            perf,
+            power,
+            accuracy_rate,
+        }
+    }
+
+    /// The metrics, by name.
+    pub fn metrics(&self) -> Vec<(&'static str, Option<&MetricDiff>)> {
+        vec![("perf", Some(&self.perf)),
+             ("power", self.power.as_ref()),
+             ("accuracy_rate", self.accuracy_rate.as_ref())]
+    }
+
+    /// The changes that exceed `thresholds`.
+    pub fn regressions(&self, thresholds: &Thresholds) -> Vec<Regression> {
+        let checks = [(thresholds.perf_drop, Direction::Drop),
+                      (thresholds.power_increase, Direction::Increase),
+                      (thresholds.accuracy_rate_drop, Direction::Drop)];
+        self.metrics().into_iter().zip(checks.iter())
+            .filter_map(|((metric, diff), &(limit, direction))| {
+                let (diff, limit) = (diff?, limit?);
+                let relative = diff.relative();
+                let exceeded = match direction {
+                    Direction::Drop => -relative > limit,
+                    Direction::Increase => relative > limit,
+                };
+                let significant = diff.p_value.is_none_or(|p| p < thresholds.alpha);
+                if exceeded && significant {
+                    Some(Regression { metric, relative, p_value: diff.p_value })
+                } else {
+                    None
+                }
+            })
+            .collect()
+    }
+}
+
+/// Which way a metric gets worse.
+#[derive(Clone, Copy)]
+enum Direction {
+    Drop,
+    Increase,
+}
+
+/// Limits on how much worse a candidate log may be than its baseline.
+///
+/// A change is only a regression if it's statistically significant, unless there are too few
+/// records to tell.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Thresholds {
+    perf_drop: Option<f64>,
+    power_increase: Option<f64>,
+    accuracy_rate_drop: Option<f64>,
+    alpha: f64,
+}
+
+impl Default for Thresholds {
+    fn default() -> Thresholds {
+        Thresholds { perf_drop: None, power_increase: None, accuracy_rate_drop: None, alpha: 0.05 }
+    }
+}
+
+impl Thresholds {
+    /// No limits, with a significance level of 0.05.
+    pub fn new() -> Thresholds {
+        Thresholds::default()
+    }
+
+    /// Fail if performance drops by more than `fraction` (e.g. 0.05 for 5%).
+    pub fn perf_drop(mut self, fraction: f64) -> Thresholds {
+        self.perf_drop = Some(fraction);
+        self
+    }
+
+    /// Fail if power increases by more than `fraction`.
+    pub fn power_increase(mut self, fraction: f64) -> Thresholds {
+        self.power_increase = Some(fraction);
+        self
+    }
+
+    /// Fail if accuracy rate drops by more than `fraction`.
+    pub fn accuracy_rate_drop(mut self, fraction: f64) -> Thresholds {
+        self.accuracy_rate_drop = Some(fraction);
+        self
+    }
+
+    /// Only count changes with a p-value below `alpha` as significant.
+    pub fn alpha(mut self, alpha: f64) -> Thresholds {
+        self.alpha = alpha;
+        self
+    }
+}
+
+/// A metric that changed by more than its threshold.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Regression {
+    pub metric: &'static str,
+    /// The change relative to the baseline.
+    pub relative: f64,
+    pub p_value: Option<f64>,
+}
+
+impl fmt::Display for Regression {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{} changed by {:+.2}%", self.metric, self.relative * 100.0)?;
+        match self.p_value {
+            Some(p) => write!(f, " (p = {:.4})", p),
+            None => write!(f, " (too few records to test significance)"),
+        }
+    }
+}
+
+/// The layout of `LogDiff` output.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum DiffFormat {
+    /// A table of metrics, followed by any regressions.
+    #[default]
+    Text,
+    /// A single JSON object.
+    Json,
+}
+
+fn json_number(v: f64) -> String {
+    if v.is_finite() { format!("{:?}", v) } else { "null".to_string() }
+}
+
+impl DiffFormat {
+    /// Write a diff and the regressions found in it.
+    pub fn write_diff(self,
+                      diff: &LogDiff,
+                      regressions: &[Regression],
+                      w: &mut dyn Write) -> io::Result<()> {
+        let text = match self {
+            DiffFormat::Text => {
+                let mut text = format!("aligned {} records by {} ({} baseline and {} candidate \
+                                        records unmatched)\n\n",
+                                       diff.aligned, diff.alignment.name(),
+                                       diff.unmatched_baseline, diff.unmatched_candidate);
+                text.push_str(&format!("{:<15} {:>20} {:>20} {:>10} {:>10}\n",
+                                       "metric", "baseline", "candidate", "change", "p-value"));
+                for (name, m) in diff.metrics() {
+                    if let Some(m) = m {
+                        let p = m.p_value.map_or("-".to_string(), |p| format!("{:.4}", p));
+                        text.push_str(&format!("{:<15} {:>20.6} {:>20.6} {:>9.2}% {:>10}\n",
+                                               name, m.baseline, m.candidate,
+                                               m.relative() * 100.0, p));
+                    }
+                }
+                for r in regressions {
+                    text.push_str(&format!("\nREGRESSION: {}", r));
+                }
+                if !regressions.is_empty() {
+                    text.push('\n');
+                }
+                text
+            }
+            DiffFormat::Json => {
+                let metrics: Vec<String> = diff.metrics().into_iter().map(|(name, m)| match m {
+                    Some(m) => format!("{}:{{\"baseline\":{},\"candidate\":{},\"relative\":{},\
+                                        \"p_value\":{}}}",
+                                       json_string(name), json_number(m.baseline),
+                                       json_number(m.candidate), json_number(m.relative()),
+                                       m.p_value.map_or("null".to_string(), json_number)),
+                    None => format!("{}:null", json_string(name)),
+                }).collect();
+                let regressions: Vec<String> = regressions.iter()
+                    .map(|r| format!("{{\"metric\":{},\"relative\":{},\"p_value\":{}}}",
+                                     json_string(r.metric), json_number(r.relative),
+                                     r.p_value.map_or("null".to_string(), json_number)))
+                    .collect();
+                format!("{{\"alignment\":{},\"aligned\":{},\"unmatched_baseline\":{},\
+                         \"unmatched_candidate\":{},\"metrics\":{{{}}},\"regressions\":[{}]}}\n",
+                        json_string(diff.alignment.name()), diff.aligned,
+                        diff.unmatched_baseline, diff.unmatched_candidate,
+                        metrics.join(","), regressions.join(","))
+            }
+        };
+        w.write_all(text.as_bytes())
+    }
+}
+
+/// The two-sided p-value of Welch's t-test for a difference in the means of two samples.
+///
+/// Non-finite values are ignored. Returns `None` if either sample has fewer than two values.
+pub fn welch_t_test(a: &[f64], b: &[f64]) -> Option<f64> {
+    let (na, ma, va) = sample_stats(a)?;
+    let (nb, mb, vb) = sample_stats(b)?;
+    let (sa, sb) = (va / na, vb / nb);
+    if sa + sb == 0.0 {
+        // no variance, so any difference is certain
+        return Some(if ma == mb { 1.0 } else { 0.0 });
+    }
+    let t = (ma - mb) / (sa + sb).sqrt();
+    let df = (sa + sb).powi(2) / (sa * sa / (na - 1.0) + sb * sb / (nb - 1.0));
+    Some(incomplete_beta(df / 2.0, 0.5, df / (df + t * t)))
+}
+
+/// The count, mean, and sample variance of the finite values, if there are at least two.
+fn sample_stats(values: &[f64]) -> Option<(f64, f64, f64)> {
+    let finite: Vec<f64> = values.iter().cloned().filter(|v| v.is_finite()).collect();
+    if finite.len() < 2 {
+        return None;
+    }
+    let n = finite.len() as f64;
+    let mean = finite.iter().sum::<f64>() / n;
+    let var = finite.iter().map(|v| (v - mean).powi(2)).sum::<f64>() / (n - 1.0);
+    Some((n, mean, var))
+}
+
+/// The natural log of the gamma function (Lanczos approximation), for `x > 0`.
+fn ln_gamma(x: f64) -> f64 {
+    const COEFFS: [f64; 9] = [0.999_999_999_999_809_9, 676.520_368_121_885_1,
+                              -1_259.139_216_722_402_8, 771.323_428_777_653_1,
+                              -176.615_029_162_140_6, 12.507_343_278_686_905,
+                              -0.138_571_095_265_720_12, 9.984_369_578_019_572e-6,
+                              1.505_632_735_149_311_6e-7];
+    if x < 0.5 {
+        // reflection formula
+        let pi = ::std::f64::consts::PI;
+        return (pi / (pi * x).sin()).ln() - ln_gamma(1.0 - x);
+    }
+    let x = x - 1.0;
+    let t = x + 7.5;
+    let sum = COEFFS[1..].iter().enumerate()
+        .fold(COEFFS[0], |sum, (i, c)| sum + c / (x + i as f64 + 1.0));
+    0.5 * (2.0 * ::std::f64::consts::PI).ln() + (x + 0.5) * t.ln() - t + sum.ln()
+}
+
+/// The regularized incomplete beta function `I_x(a, b)`.
+fn incomplete_beta(a: f64, b: f64, x: f64) -> f64 {
+    if x <= 0.0 {
+        return 0.0;
+    }
+    if x >= 1.0 {
+        return 1.0;
+    }
+    let front = (ln_gamma(a + b) - ln_gamma(a) - ln_gamma(b) + a * x.ln() + b * (1.0 - x).ln())
+        .exp();
+    // the continued fraction converges quickly on this side
+    if x < (a + 1.0) / (a + b + 2.0) {
+        front * beta_continued_fraction(a, b, x) / a
+    } else {
+        1.0 - front * beta_continued_fraction(b, a, 1.0 - x) / b
+    }
+}
+
+/// Evaluate the continued fraction for the incomplete beta function (modified Lentz's method).
+fn beta_continued_fraction(a: f64, b: f64, x: f64) -> f64 {
+    const TINY: f64 = 1e-300;
+    let clamp = |v: f64| if v.abs() < TINY { TINY } else { v };
+    let mut c = 1.0;
+    let mut d = 1.0 / clamp(1.0 - (a + b) * x / (a + 1.0));
+    let mut h = d;
+    for m in 1..300 {
+        let m = m as f64;
+        let m2 = 2.0 * m;
+        // even step
+        let aa = m * (b - m) * x / ((a + m2 - 1.0) * (a + m2));
+        d = 1.0 / clamp(1.0 + aa * d);
+        c = clamp(1.0 + aa / c);
+        h *= d * c;
+        // odd step
+        let aa = -(a + m) * (a + b + m) * x / ((a + m2) * (a + m2 + 1.0));
+        d = 1.0 / clamp(1.0 + aa * d);
+        c = clamp(1.0 + aa / c);
+        let delta = d * c;
+        h *= delta;
+        if (delta - 1.0).abs() < 1e-15 {
+            break;
+        }
+    }
+    h
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use log::{LogFormat, LogPower, LogVariant};
+    use log::read::AnyLogReader;
+
+    fn assert_close(a: f64, b: f64, tolerance: f64) {
+        assert!((a - b).abs() <= tolerance, "{} != {}", a, b);
+    }
+
+    /// Records with the given tags, each taking 1 ms plus `jitter` ns times a small pattern.
+    fn records(tags: &[u64], work: u64, energy: u64, jitter: u64) -> Vec<LogRecord> {
+        let mut start = 0;
+        tags.iter().enumerate().map(|(i, &tag)| {
+            let time = 1000000 + jitter * (i as u64 % 3);
+            let seconds = time as f64 / ONE_BILLION;
+            let r = LogRecord {
+                id: i as u64,
+                user_tag: tag,
+                work,
+                start_time: start,
+                end_time: start + time,
+                instant_perf: work as f64 / seconds,
+                power: Some(LogPower {
+                    start_energy: i as u64 * energy,
+                    end_energy: (i as u64 + 1) * energy,
+                    instant_power: energy as f64 / seconds / ONE_MILLION,
+                    ..LogPower::default()
+                }),
+                ..LogRecord::default()
+            };
+            start += time;
+            r
+        }).collect()
+    }
+
+    #[test]
+    fn test_incomplete_beta() {
+        for &x in &[0.0, 0.1, 0.5, 0.9, 1.0] {
+            assert_close(incomplete_beta(1.0, 1.0, x), x, 1e-12);
+            assert_close(incomplete_beta(3.0, 1.0, x), x.powi(3), 1e-12);
+            assert_close(incomplete_beta(2.5, 4.0, x) + incomplete_beta(4.0, 2.5, 1.0 - x),
+                         1.0, 1e-12);
+        }
+        assert_close(ln_gamma(5.0), 24f64.ln(), 1e-12);
+        assert_close(ln_gamma(0.5), ::std::f64::consts::PI.sqrt().ln(), 1e-12);
+    }
+
+    #[test]
+    fn test_welch_t_test() {
+        let a = [1.0, 2.0, 3.0, 4.0, 5.0];
+        let b = [3.0, 4.0, 5.0, 6.0, 7.0];
+        // t = -2 with 8 degrees of freedom
+        assert_close(welch_t_test(&a, &b).unwrap(), 0.080516, 1e-6);
+        assert_close(welch_t_test(&a, &a).unwrap(), 1.0, 1e-12);
+        assert_eq!(welch_t_test(&[1.0, 1.0], &[2.0, 2.0]), Some(0.0));
+        assert_eq!(welch_t_test(&[1.0], &b), None);
+    }
+
+    #[test]
+    fn test_index() {
+        let base = records(&[0, 1, 2, 3, 4, 5], 10, 1000, 1000);
+        let cand = records(&[0, 1, 2, 3, 4, 5, 6], 9, 1100, 1000);
+        let diff = LogDiff::new(&base, &cand, Alignment::Index);
+        assert_eq!((diff.aligned, diff.unmatched_baseline, diff.unmatched_candidate), (6, 0, 1));
+        assert_close(diff.perf.relative(), -0.1, 1e-12);
+        assert_close(diff.power.unwrap().relative(), 0.1, 1e-12);
+        assert!(diff.perf.p_value.unwrap() < 0.001);
+        assert!(diff.accuracy_rate.is_none());
+
+        assert!(diff.regressions(&Thresholds::new()).is_empty());
+        let thresholds = Thresholds::new().perf_drop(0.05).power_increase(0.2);
+        let regressions = diff.regressions(&thresholds);
+        assert_eq!(regressions.len(), 1);
+        assert_eq!(regressions[0].metric, "perf");
+        assert!(regressions[0].to_string().starts_with("perf changed by -10.00% (p = "));
+        // not significant at this level
+        assert!(diff.regressions(&thresholds.alpha(0.0)).is_empty());
+    }
+
+    #[test]
+    fn test_zero_thresholds() {
+        let base = records(&[0, 1, 2, 3, 4, 5], 10, 1000, 1000);
+        let cand = records(&[0, 1, 2, 3, 4, 5], 9, 1100, 1000);
+        let thresholds = Thresholds::new().perf_drop(0.0).power_increase(0.0);
+        // any significant drop in perf or increase in power
+        let regressions = LogDiff::new(&base, &cand, Alignment::Index).regressions(&thresholds);
+        let metrics: Vec<&str> = regressions.iter().map(|r| r.metric).collect();
+        assert_eq!(metrics, vec!["perf", "power"]);
+        // but not the reverse
+        let diff = LogDiff::new(&cand, &base, Alignment::Index);
+        assert!(diff.perf.relative() > 0.0 && diff.power.unwrap().relative() < 0.0);
+        assert!(diff.regressions(&thresholds).is_empty());
+    }
+
+    #[test]
+    fn test_tag() {
+        let base = records(&[7, 1, 2, 1], 10, 1000, 0);
+        let mut cand = records(&[1, 2, 1, 3], 10, 1000, 0);
+        cand[2].work = 20;
+        let diff = LogDiff::new(&base, &cand, Alignment::Tag);
+        assert_eq!((diff.aligned, diff.unmatched_baseline, diff.unmatched_candidate), (3, 1, 1));
+        // the second tag 1 record has twice the work
+        assert_close(diff.perf.relative(), 1.0 / 3.0, 1e-12);
+    }
+
+    #[test]
+    fn test_formats() {
+        let base = records(&[0, 1, 2], 10, 1000, 1000);
+        let cand = records(&[0, 1, 2], 5, 1000, 1000);
+        let diff = LogDiff::new(&base, &cand, Alignment::Index);
+        let regressions = diff.regressions(&Thresholds::new().perf_drop(0.1));
+        let mut text = Vec::new();
+        DiffFormat::Text.write_diff(&diff, &regressions, &mut text).unwrap();
+        let text = String::from_utf8(text).unwrap();
+        assert!(text.starts_with("aligned 3 records by index (0 baseline and 0 candidate"));
+        assert!(text.contains("\nperf "));
+        assert!(!text.contains("accuracy_rate"));
+        assert!(text.ends_with("\nREGRESSION: perf changed by -50.00% (p = 0.0000)\n"));
+
+        let mut json = Vec::new();
+        DiffFormat::Json.write_diff(&diff, &regressions, &mut json).unwrap();
+        let json = String::from_utf8(json).unwrap();
+        assert!(json.starts_with("{\"alignment\":\"index\",\"aligned\":3,"));
+        assert!(json.contains("\"accuracy_rate\":null}"));
+        assert!(json.contains("\"regressions\":[{\"metric\":\"perf\",\"relative\":-0.5,"));
+    }
+
+    #[test]
+    fn test_log_formats() {
+        // logs in different formats read back to the same records
+        let records = records(&[0, 1, 2, 3], 10, 1000, 1000);
+        let read = |format: LogFormat| -> Vec<LogRecord> {
+            let mut log = Vec::new();
+            format.write_header(LogVariant::Pow, &mut log).unwrap();
+            for r in &records {
+                format.write_record(r, &mut log).unwrap();
+            }
+            AnyLogReader::new(&log[..]).unwrap().collect::<Result<_, _>>().unwrap()
+        };
+        let diff = LogDiff::new(&read(LogFormat::Csv), &read(LogFormat::JsonLines),
+                                Alignment::Index);
+        assert_eq!(diff.aligned, 4);
+        assert_eq!(diff.perf.relative(), 0.0);
+        assert_eq!(diff.power.unwrap().relative(), 0.0);
+    }
+}
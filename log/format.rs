@@ -0,0 +1,302 @@
+use std::io::{self, Write};
+use super::{binary, LogAccuracy, LogMetadata, LogPower, LogRecord, LogVariant};
+
+/// Widths of the fixed-width columns common to all variants.
+const PERF_WIDTHS: [usize; 12] = [6, 6, 11, 11, 11, 15, 15, 20, 20, 15, 15, 15];
+/// Width of the accuracy and power columns.
+const EXTRA_WIDTH: usize = 15;
+
+/// The layout of heartbeat logs.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum LogFormat {
+    /// Whitespace-separated columns padded to fixed widths, with a header row.
+    ///
+    /// Values wider than their column push the following columns right.
+    #[default]
+    FixedWidth,
+    /// Comma-separated values with a header row.
+    Csv,
+    /// One JSON object per record, keyed by column name, without a header.
+    ///
+    /// Non-finite rates (e.g., for zero-length heartbeats) are written as `null`.
+    JsonLines,
+    /// The compact binary format described in the `binary` module, for high heartbeat rates.
+    ///
+    /// Records are written without allocating; read them with `binary::BinaryLogReader`.
+    Binary,
+}
+
+/// A log column value.
+pub(crate) enum Value {
+    Int(u64),
+    Float(f64),
+}
+
+/// Visit a record's values in column order.
+pub(crate) fn visit_values<F: FnMut(Value)>(r: &LogRecord, mut f: F) {
+    for &v in &[r.id, r.user_tag, r.global_work, r.window_work, r.work,
+                r.global_time, r.window_time, r.start_time, r.end_time] {
+        f(Value::Int(v));
+    }
+    for &v in &[r.global_perf, r.window_perf, r.instant_perf] {
+        f(Value::Float(v));
+    }
+    if let Some(ref a) = r.accuracy {
+        for &v in &[a.global_accuracy, a.window_accuracy, a.accuracy] {
+            f(Value::Int(v));
+        }
+        for &v in &[a.global_accuracy_rate, a.window_accuracy_rate, a.instant_accuracy_rate] {
+            f(Value::Float(v));
+        }
+    }
+    if let Some(ref p) = r.power {
+        for &v in &[p.global_energy, p.window_energy, p.start_energy, p.end_energy] {
+            f(Value::Int(v));
+        }
+        for &v in &[p.global_power, p.window_power, p.instant_power] {
+            f(Value::Float(v));
+        }
+    }
+}
+
+/// Reads values in column order from a function returning each value as a `u64`.
+struct Values<F>(F);
+
+impl<F: FnMut() -> u64> Values<F> {
+    fn int(&mut self) -> u64 {
+        (self.0)()
+    }
+
+    fn float(&mut self) -> f64 {
+        f64::from_bits(self.int())
+    }
+}
+
+/// Build a record of `variant` from its values in column order, with rates as `f64` bits (the
+/// inverse of `visit_values`).
+pub(crate) fn record_from_values<F: FnMut() -> u64>(variant: LogVariant, next: F) -> LogRecord {
+    let mut v = Values(next);
+    let mut r = LogRecord {
+        id: v.int(),
+        user_tag: v.int(),
+        global_work: v.int(),
+        window_work: v.int(),
+        work: v.int(),
+        global_time: v.int(),
+        window_time: v.int(),
+        start_time: v.int(),
+        end_time: v.int(),
+        global_perf: v.float(),
+        window_perf: v.float(),
+        instant_perf: v.float(),
+        accuracy: None,
+        power: None,
+    };
+    if variant.has_accuracy() {
+        r.accuracy = Some(LogAccuracy {
+            global_accuracy: v.int(),
+            window_accuracy: v.int(),
+            accuracy: v.int(),
+            global_accuracy_rate: v.float(),
+            window_accuracy_rate: v.float(),
+            instant_accuracy_rate: v.float(),
+        });
+    }
+    if variant.has_power() {
+        r.power = Some(LogPower {
+            global_energy: v.int(),
+            window_energy: v.int(),
+            start_energy: v.int(),
+            end_energy: v.int(),
+            global_power: v.float(),
+            window_power: v.float(),
+            instant_power: v.float(),
+        });
+    }
+    r
+}
+
+fn values(r: &LogRecord) -> Vec<Value> {
+    let mut values = Vec::new();
+    visit_values(r, |v| values.push(v));
+    values
+}
+
+pub(crate) fn json_string(s: &str) -> String {
+    let mut out = String::from("\"");
+    for c in s.chars() {
+        match c {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            c if (c as u32) < 0x20 => out.push_str(&format!("\\u{:04x}", c as u32)),
+            c => out.push(c),
+        }
+    }
+    out.push('"');
+    out
+}
+
+fn width(column: usize) -> usize {
+    PERF_WIDTHS.get(column).cloned().unwrap_or(EXTRA_WIDTH)
+}
+
+/// Join fields into a fixed-width line, padding all but the last.
+fn fixed_width<I: Iterator<Item = String>>(fields: I, count: usize) -> String {
+    let mut line = String::new();
+    for (i, field) in fields.enumerate() {
+        if i + 1 < count {
+            line.push_str(&format!("{:<1$} ", field, width(i)));
+        } else {
+            line.push_str(&field);
+        }
+    }
+    line.push('\n');
+    line
+}
+
+impl LogFormat {
+    /// Write the header for a log of `variant`, if the format has one.
+    pub fn write_header(self, variant: LogVariant, w: &mut dyn Write) -> io::Result<()> {
+        let columns = variant.columns();
+        let header = match self {
+            LogFormat::FixedWidth => {
+                fixed_width(columns.iter().map(|c| c.to_string()), columns.len())
+            }
+            LogFormat::Csv => columns.join(",") + "\n",
+            LogFormat::JsonLines => return Ok(()),
+            LogFormat::Binary => return binary::write_header(variant, w),
+        };
+        w.write_all(header.as_bytes())
+    }
+
+    /// Write a metadata preamble followed by the header for a log of `variant`.
+    ///
+    /// Like the header alone, it's written with a single `write_all`.
+    ///
+    /// Text logs start with `# key: value` comment lines, JSON Lines logs with a
+    /// `{"metadata": {...}}` object, and binary logs with a metadata block.
+    pub fn write_header_with_metadata(self,
+                                      variant: LogVariant,
+                                      metadata: &LogMetadata,
+                                      w: &mut dyn Write) -> io::Result<()> {
+        match self {
+            LogFormat::FixedWidth | LogFormat::Csv => {
+                let mut buf = metadata.to_text("# ").into_bytes();
+                self.write_header(variant, &mut buf)?;
+                w.write_all(&buf)
+            }
+            LogFormat::JsonLines => {
+                let fields: Vec<String> = metadata.entries().iter()
+                    .map(|(k, v)| format!("{}:{}", json_string(k), json_string(v)))
+                    .collect();
+                w.write_all(format!("{{\"metadata\":{{{}}}}}\n", fields.join(",")).as_bytes())
+            }
+            LogFormat::Binary => binary::write_header_with_metadata(variant, Some(metadata), w),
+        }
+    }
+
+    /// Write a record.
+    pub fn write_record(self, r: &LogRecord, w: &mut dyn Write) -> io::Result<()> {
+        if self == LogFormat::Binary {
+            return binary::write_record(r, w);
+        }
+        let values = values(r);
+        let line = match self {
+            LogFormat::FixedWidth => {
+                let fields = values.iter().map(|v| match *v {
+                    Value::Int(i) => i.to_string(),
+                    Value::Float(f) => format!("{:.6}", f),
+                });
+                fixed_width(fields, values.len())
+            }
+            LogFormat::Csv => {
+                let fields: Vec<String> = values.iter().map(|v| match *v {
+                    Value::Int(i) => i.to_string(),
+                    Value::Float(f) => f.to_string(),
+                }).collect();
+                fields.join(",") + "\n"
+            }
+            LogFormat::JsonLines => {
+                let columns = r.variant().columns();
+                let fields: Vec<String> = columns.iter().zip(&values).map(|(c, v)| match *v {
+                    Value::Int(i) => format!("\"{}\":{}", c, i),
+                    Value::Float(f) if f.is_finite() => format!("\"{}\":{:?}", c, f),
+                    Value::Float(_) => format!("\"{}\":null", c),
+                }).collect();
+                format!("{{{}}}\n", fields.join(","))
+            }
+            LogFormat::Binary => unreachable!(),
+        };
+        w.write_all(line.as_bytes())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use log::LogPower;
+
+    fn record() -> LogRecord {
+        LogRecord {
+            id: 3,
+            user_tag: 7,
+            global_work: 4,
+            window_work: 2,
+            work: 1,
+            global_time: 4000,
+            window_time: 2000,
+            start_time: 3000,
+            end_time: 4000,
+            global_perf: 1000000.0,
+            window_perf: 1000000.0,
+            instant_perf: 1000000.0,
+            accuracy: None,
+            power: Some(LogPower {
+                global_energy: 10,
+                window_energy: 5,
+                start_energy: 20,
+                end_energy: 22,
+                global_power: 0.0025,
+                window_power: 0.0025,
+                instant_power: f64::INFINITY,
+            }),
+        }
+    }
+
+    fn write(format: LogFormat) -> String {
+        let mut buf = Vec::new();
+        format.write_header(LogVariant::Pow, &mut buf).unwrap();
+        format.write_record(&record(), &mut buf).unwrap();
+        String::from_utf8(buf).unwrap()
+    }
+
+    #[test]
+    fn test_fixed_width() {
+        let log = write(LogFormat::FixedWidth);
+        let lines: Vec<&str> = log.lines().collect();
+        assert!(lines[0].starts_with("HB     Tag    Global_Work "));
+        assert!(lines[0].ends_with("Window_Pwr      Instant_Pwr"));
+        assert!(lines[1].starts_with("3      7      4           2           1           4000 "));
+        assert!(lines[1].ends_with("0.002500        0.002500        inf"));
+    }
+
+    #[test]
+    fn test_csv() {
+        let log = write(LogFormat::Csv);
+        let lines: Vec<&str> = log.lines().collect();
+        assert_eq!(lines[0], LogVariant::Pow.columns().join(","));
+        assert_eq!(lines[1], "3,7,4,2,1,4000,2000,3000,4000,1000000,1000000,1000000,\
+                              10,5,20,22,0.0025,0.0025,inf");
+    }
+
+    #[test]
+    fn test_json_lines() {
+        let log = write(LogFormat::JsonLines);
+        assert_eq!(log, "{\"HB\":3,\"Tag\":7,\"Global_Work\":4,\"Window_Work\":2,\"Work\":1,\
+                         \"Global_Time\":4000,\"Window_Time\":2000,\"Start_Time\":3000,\
+                         \"End_Time\":4000,\"Global_Perf\":1000000.0,\"Window_Perf\":1000000.0,\
+                         \"Instant_Perf\":1000000.0,\"Global_Energy\":10,\"Window_Energy\":5,\
+                         \"Start_Energy\":20,\"End_Energy\":22,\"Global_Pwr\":0.0025,\
+                         \"Window_Pwr\":0.0025,\"Instant_Pwr\":null}\n");
+    }
+}
@@ -0,0 +1,331 @@
+//! Read JSON Lines logs.
+//!
+//! Each line is a flat JSON object keyed by column name, as written by `LogFormat::JsonLines`,
+//! with `null` rates read as NaN. A `{"metadata": {...}}` object may precede the records.
+
+use std::fs::File;
+use std::io::{self, BufRead, BufReader};
+use std::path::Path;
+use super::read::{parse_fields, ReadError};
+use super::{LogMetadata, LogRecord, LogVariant};
+
+/// A JSON value in a log line.
+enum Json {
+    /// A number, kept as text to be parsed as its column's type.
+    Number(String),
+    Null,
+    String(String),
+    Object(Vec<(String, Json)>),
+}
+
+/// Parses a line holding a single JSON object.
+struct Parser<'a> {
+    text: &'a str,
+    pos: usize,
+}
+
+impl<'a> Parser<'a> {
+    fn peek(&mut self) -> Option<u8> {
+        let bytes = self.text.as_bytes();
+        while bytes.get(self.pos).is_some_and(|b| b.is_ascii_whitespace()) {
+            self.pos += 1;
+        }
+        bytes.get(self.pos).cloned()
+    }
+
+    fn unexpected(&mut self) -> String {
+        match self.text[self.pos..].chars().next() {
+            Some(c) => format!("unexpected '{}' at column {}", c, self.pos + 1),
+            None => "unexpected end of line".to_string(),
+        }
+    }
+
+    fn expect(&mut self, b: u8) -> Result<(), String> {
+        if self.peek() != Some(b) {
+            return Err(self.unexpected());
+        }
+        self.pos += 1;
+        Ok(())
+    }
+
+    fn value(&mut self) -> Result<Json, String> {
+        match self.peek() {
+            Some(b'{') => self.object().map(Json::Object),
+            Some(b'"') => self.string().map(Json::String),
+            Some(b'n') if self.text[self.pos..].starts_with("null") => {
+                self.pos += 4;
+                Ok(Json::Null)
+            }
+            Some(b'-') | Some(b'0'..=b'9') => {
+                let start = self.pos;
+                let len = self.text[start..]
+                    .find(|c: char| !(c.is_ascii_digit() || "+-.eE".contains(c)))
+                    .unwrap_or(self.text.len() - start);
+                self.pos += len;
+                Ok(Json::Number(self.text[start..self.pos].to_string()))
+            }
+            _ => Err(self.unexpected()),
+        }
+    }
+
+    fn object(&mut self) -> Result<Vec<(String, Json)>, String> {
+        self.expect(b'{')?;
+        let mut entries = Vec::new();
+        if self.peek() == Some(b'}') {
+            self.pos += 1;
+            return Ok(entries);
+        }
+        loop {
+            let key = self.string()?;
+            self.expect(b':')?;
+            entries.push((key, self.value()?));
+            match self.peek() {
+                Some(b',') => self.pos += 1,
+                Some(b'}') => {
+                    self.pos += 1;
+                    return Ok(entries);
+                }
+                _ => return Err(self.unexpected()),
+            }
+        }
+    }
+
+    fn string(&mut self) -> Result<String, String> {
+        self.expect(b'"')?;
+        let mut out = String::new();
+        let mut chars = self.text[self.pos..].char_indices();
+        while let Some((i, c)) = chars.next() {
+            match c {
+                '"' => {
+                    self.pos += i + 1;
+                    return Ok(out);
+                }
+                '\\' => {
+                    let escaped = match chars.next() {
+                        Some((_, 'n')) => '\n',
+                        Some((_, 'r')) => '\r',
+                        Some((_, 't')) => '\t',
+                        Some((_, 'b')) => '\u{8}',
+                        Some((_, 'f')) => '\u{c}',
+                        Some((_, 'u')) => {
+                            let hex: String = chars.by_ref().take(4).map(|(_, c)| c).collect();
+                            u32::from_str_radix(&hex, 16).ok()
+                                .and_then(std::char::from_u32)
+                                .unwrap_or(std::char::REPLACEMENT_CHARACTER)
+                        }
+                        Some((_, c)) => c,
+                        None => break,
+                    };
+                    out.push(escaped);
+                }
+                c => out.push(c),
+            }
+        }
+        Err("unterminated string".to_string())
+    }
+}
+
+/// Parse a line holding a single JSON object into its entries.
+fn parse_object(text: &str) -> Result<Vec<(String, Json)>, String> {
+    let mut parser = Parser { text, pos: 0 };
+    let entries = parser.object()?;
+    if parser.peek().is_some() {
+        return Err(parser.unexpected());
+    }
+    Ok(entries)
+}
+
+/// Detect the heartbeat variant that wrote a record from its keys.
+fn detect_variant(entries: &[(String, Json)]) -> Result<LogVariant, ReadError> {
+    LogVariant::ALL.iter()
+        .cloned()
+        .find(|v| {
+            let columns = v.columns();
+            columns.len() == entries.len() && entries.iter().all(|e| columns.contains(&&*e.0))
+        })
+        .ok_or_else(|| {
+            let keys: Vec<&str> = entries.iter().map(|e| e.0.as_str()).collect();
+            ReadError::UnknownHeader(keys.join(" "))
+        })
+}
+
+/// Build a record of `variant` from a line's entries, numbered `line` for errors.
+fn parse_record(variant: LogVariant,
+                entries: &[(String, Json)],
+                line: usize) -> Result<LogRecord, ReadError> {
+    let columns = variant.columns();
+    if let Some(e) = entries.iter().find(|e| !columns.contains(&&*e.0)) {
+        return Err(ReadError::Malformed { line, msg: format!("unknown column {}", e.0) });
+    }
+    let mut fields = Vec::with_capacity(columns.len());
+    for column in columns {
+        // parse_fields reports the first missing column
+        let value = match entries.iter().find(|e| e.0 == column) {
+            Some(e) => &e.1,
+            None => break,
+        };
+        fields.push(match *value {
+            Json::Number(ref n) => n.as_str(),
+            Json::Null => "NaN",
+            Json::String(ref s) => s.as_str(),
+            Json::Object(_) => "{...}",
+        });
+    }
+    parse_fields(variant, fields.into_iter(), line)
+}
+
+/// Iterates over the records of a JSON Lines log, detecting its variant from the keys of the
+/// first record, or from its metadata if it has no records.
+///
+/// Blank lines are skipped.
+pub struct JsonLogReader<R> {
+    lines: io::Lines<R>,
+    line: usize,
+    variant: LogVariant,
+    metadata: LogMetadata,
+    first: Option<Result<LogRecord, ReadError>>,
+}
+
+impl JsonLogReader<BufReader<File>> {
+    /// Open the log file at `path`.
+    pub fn open<P: AsRef<Path>>(path: P) -> Result<JsonLogReader<BufReader<File>>, ReadError> {
+        JsonLogReader::new(BufReader::new(File::open(path)?))
+    }
+}
+
+impl<R: BufRead> JsonLogReader<R> {
+    /// Read any metadata and the first record from `reader`.
+    pub fn new(reader: R) -> Result<JsonLogReader<R>, ReadError> {
+        let mut lines = reader.lines();
+        let mut line = 0;
+        let mut metadata = LogMetadata::default();
+        loop {
+            line += 1;
+            let text = match lines.next() {
+                Some(text) => text?,
+                None => {
+                    let variant = metadata.variant().ok_or(ReadError::MissingHeader)?;
+                    return Ok(JsonLogReader { lines, line, variant, metadata, first: None });
+                }
+            };
+            if text.trim().is_empty() {
+                continue;
+            }
+            let entries = parse_object(&text)
+                .map_err(|msg| ReadError::Malformed { line, msg })?;
+            match entries.first() {
+                Some(&(ref key, Json::Object(ref meta))) if key == "metadata" => {
+                    for (key, value) in meta {
+                        if let Json::String(ref value) = *value {
+                            metadata.insert(key, value);
+                        }
+                    }
+                }
+                _ => {
+                    let variant = detect_variant(&entries)?;
+                    let first = Some(parse_record(variant, &entries, line));
+                    return Ok(JsonLogReader { lines, line, variant, metadata, first });
+                }
+            }
+        }
+    }
+
+    /// The heartbeat variant that wrote the log.
+    pub fn variant(&self) -> LogVariant {
+        self.variant
+    }
+
+    /// The log's metadata, which is empty if it has none.
+    pub fn metadata(&self) -> &LogMetadata {
+        &self.metadata
+    }
+}
+
+impl<R: BufRead> Iterator for JsonLogReader<R> {
+    type Item = Result<LogRecord, ReadError>;
+
+    fn next(&mut self) -> Option<Result<LogRecord, ReadError>> {
+        if let Some(first) = self.first.take() {
+            return Some(first);
+        }
+        loop {
+            self.line += 1;
+            let line = self.line;
+            match self.lines.next()? {
+                Ok(ref text) if text.trim().is_empty() => continue,
+                Ok(text) => {
+                    return Some(parse_object(&text)
+                        .map_err(|msg| ReadError::Malformed { line, msg })
+                        .and_then(|entries| parse_record(self.variant, &entries, line)));
+                }
+                Err(e) => return Some(Err(e.into())),
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use hbs_acc_pow::HeartbeatAccPow;
+    use hbs_common::TestLog;
+    use log::{LogFormat, LogOptions};
+
+    #[test]
+    fn test_round_trip() {
+        let log = TestLog::default();
+        let options = LogOptions::new().format(LogFormat::JsonLines).metadata(true);
+        let mut hb = HeartbeatAccPow::with_log_options(4, None, log.sink(), options).unwrap();
+        // a zero-length heartbeat has non-finite rates, written as null
+        hb.heartbeat(0, 1, 1000, 1000, 2, 0, 500);
+        for tag in 1..3 {
+            hb.heartbeat(tag, 1, tag * 1000, (tag + 1) * 1000, 2, tag * 500, (tag + 1) * 500);
+        }
+        hb.log_to_buffer_index().unwrap();
+        let contents = log.contents();
+        let reader = JsonLogReader::new(contents.as_bytes()).unwrap();
+        assert_eq!(reader.variant(), LogVariant::AccPow);
+        assert_eq!(reader.metadata().window_size(), Some(4));
+        let read: Vec<LogRecord> = reader.collect::<Result<_, _>>().unwrap();
+        let records: Vec<LogRecord> = hb.records().map(LogRecord::from).collect();
+        assert_eq!(read.len(), 3);
+        assert!(read[0].instant_perf.is_nan());
+        assert_eq!(read[1..], records[1..]);
+    }
+
+    #[test]
+    fn test_metadata_only() {
+        let log = "{\"metadata\":{\"variant\":\"HeartbeatPow\",\"note\":\"a \\\"b\\\"\\n\"}}\n";
+        let reader = JsonLogReader::new(log.as_bytes()).unwrap();
+        assert_eq!(reader.variant(), LogVariant::Pow);
+        assert_eq!(reader.metadata().get("note"), Some("a \"b\" "));
+        assert_eq!(reader.count(), 0);
+    }
+
+    #[test]
+    fn test_malformed() {
+        match JsonLogReader::new("\n".as_bytes()) {
+            Err(ReadError::MissingHeader) => (),
+            _ => panic!("Expected MissingHeader"),
+        }
+        match JsonLogReader::new("{\"HB\":0,\"Foo\":1}\n".as_bytes()) {
+            Err(ReadError::UnknownHeader(h)) => assert_eq!(h, "HB Foo"),
+            _ => panic!("Expected UnknownHeader"),
+        }
+        let columns = LogVariant::Heartbeat.columns();
+        let fields: Vec<String> = columns.iter().map(|c| format!("\"{}\":1", c)).collect();
+        let record = format!("{{{}}}", fields.join(","));
+        let log = format!("{}\n{{\"HB\":1,\n{}\n{}\n",
+                          record, record.replace("\"Work\":1", "\"Work\":null"),
+                          record.replace("\"HB\"", "\"Foo\""));
+        let results: Vec<_> = JsonLogReader::new(log.as_bytes()).unwrap().collect();
+        assert!(results[0].is_ok());
+        assert_eq!(results[1].as_ref().unwrap_err().to_string(),
+                   "Malformed log record on line 2: unexpected end of line");
+        assert_eq!(results[2].as_ref().unwrap_err().to_string(),
+                   "Malformed log record on line 3: invalid Work value 'NaN': \
+                    invalid digit found in string");
+        assert_eq!(results[3].as_ref().unwrap_err().to_string(),
+                   "Malformed log record on line 4: unknown column Foo");
+    }
+}
@@ -0,0 +1,167 @@
+#[cfg(unix)]
+use libc;
+#[cfg(unix)]
+use std::ffi::CStr;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use super::LogVariant;
+
+/// Metadata describing the run that wrote a log, as `key: value` entries.
+///
+/// Heartbeats write the entries `variant`, `window_size`, `clock`, `time_units`,
+/// `energy_units` (power variants only), `hostname`, `start_time` (UTC, for people) and
+/// `start_time_ns` (nanoseconds since the Unix epoch).
+/// Logs written by other tools may have other entries, or omit some.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct LogMetadata {
+    entries: Vec<(String, String)>,
+}
+
+#[cfg(unix)]
+fn hostname() -> Option<String> {
+    let mut buf = [0 as libc::c_char; 256];
+    // POSIX doesn't guarantee termination if the name is truncated
+    let rc = unsafe { libc::gethostname(buf.as_mut_ptr(), buf.len() - 1) };
+    if rc != 0 {
+        return None;
+    }
+    let name = unsafe { CStr::from_ptr(buf.as_ptr()) };
+    Some(name.to_string_lossy().into_owned())
+}
+
+#[cfg(not(unix))]
+fn hostname() -> Option<String> {
+    ::std::env::var("COMPUTERNAME").ok()
+}
+
+/// Format a time as an RFC 3339 UTC timestamp with nanoseconds.
+fn format_utc(time: SystemTime) -> String {
+    let since_epoch = time.duration_since(UNIX_EPOCH).unwrap_or_default();
+    let secs = since_epoch.as_secs();
+    let (days, rem) = ((secs / 86400) as i64, secs % 86400);
+    // civil-from-days (Howard Hinnant's algorithm)
+    let z = days + 719468;
+    let era = z.div_euclid(146097);
+    let doe = z - era * 146097;
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + if month <= 2 { 1 } else { 0 };
+    format!("{:04}-{:02}-{:02}T{:02}:{:02}:{:02}.{:09}Z",
+            year, month, day, rem / 3600, rem % 3600 / 60, rem % 60, since_epoch.subsec_nanos())
+}
+
+impl LogMetadata {
+    /// Describe a heartbeat that is starting now.
+    pub fn for_heartbeat(variant: LogVariant, window_size: usize, clock: &str) -> LogMetadata {
+        let mut meta = LogMetadata::default();
+        meta.insert("variant", variant.name());
+        meta.insert("window_size", &window_size.to_string());
+        meta.insert("clock", clock);
+        meta.insert("time_units", "ns");
+        if variant.has_power() {
+            meta.insert("energy_units", "uJ");
+        }
+        if let Some(host) = hostname() {
+            meta.insert("hostname", &host);
+        }
+        let now = SystemTime::now();
+        let since_epoch = now.duration_since(UNIX_EPOCH).unwrap_or_default();
+        meta.insert("start_time", &format_utc(now));
+        meta.insert("start_time_ns", &since_epoch.as_nanos().to_string());
+        meta
+    }
+
+    /// Add an entry, replacing any existing value for `key`.
+    ///
+    /// Keys can't contain `:` and values can't contain line breaks, which are replaced by
+    /// spaces.
+    pub fn insert(&mut self, key: &str, value: &str) {
+        let key = key.replace(':', "_");
+        let value = value.replace(['\n', '\r'], " ");
+        match self.entries.iter_mut().find(|e| e.0 == key) {
+            Some(e) => e.1 = value,
+            None => self.entries.push((key, value)),
+        }
+    }
+
+    /// Get the value of an entry.
+    pub fn get(&self, key: &str) -> Option<&str> {
+        self.entries.iter().find(|e| e.0 == key).map(|e| e.1.as_str())
+    }
+
+    /// All entries, in the order they were written.
+    pub fn entries(&self) -> &[(String, String)] {
+        &self.entries
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    pub fn variant(&self) -> Option<LogVariant> {
+        self.get("variant").and_then(LogVariant::from_name)
+    }
+
+    pub fn window_size(&self) -> Option<usize> {
+        self.get("window_size").and_then(|v| v.parse().ok())
+    }
+
+    pub fn clock(&self) -> Option<&str> {
+        self.get("clock")
+    }
+
+    pub fn energy_units(&self) -> Option<&str> {
+        self.get("energy_units")
+    }
+
+    pub fn hostname(&self) -> Option<&str> {
+        self.get("hostname")
+    }
+
+    /// The wall-clock time when the heartbeat started.
+    pub fn start_time(&self) -> Option<SystemTime> {
+        let ns: u64 = self.get("start_time_ns")?.parse().ok()?;
+        Some(UNIX_EPOCH + Duration::from_nanos(ns))
+    }
+
+    /// Parse an entry from a `key: value` line, with any comment marker already removed.
+    pub(crate) fn parse_entry(&mut self, line: &str) {
+        if let Some((key, value)) = line.split_once(':') {
+            self.insert(key.trim(), value.trim());
+        }
+    }
+
+    /// The entries as `key: value` lines.
+    pub(crate) fn to_text(&self, prefix: &str) -> String {
+        self.entries.iter().map(|(k, v)| format!("{}{}: {}\n", prefix, k, v)).collect()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_format_utc() {
+        assert_eq!(format_utc(UNIX_EPOCH), "1970-01-01T00:00:00.000000000Z");
+        let t = UNIX_EPOCH + Duration::new(951827696, 5);
+        assert_eq!(format_utc(t), "2000-02-29T12:34:56.000000005Z");
+    }
+
+    #[test]
+    fn test_for_heartbeat() {
+        let meta = LogMetadata::for_heartbeat(LogVariant::Pow, 20, "CLOCK_MONOTONIC");
+        assert_eq!(meta.variant(), Some(LogVariant::Pow));
+        assert_eq!(meta.window_size(), Some(20));
+        assert_eq!(meta.clock(), Some("CLOCK_MONOTONIC"));
+        assert_eq!(meta.energy_units(), Some("uJ"));
+        assert!(meta.start_time().unwrap() <= SystemTime::now());
+        let mut parsed = LogMetadata::default();
+        for line in meta.to_text("# ").lines() {
+            parsed.parse_entry(line.trim_start_matches('#'));
+        }
+        assert_eq!(parsed, meta);
+    }
+}
@@ -0,0 +1,312 @@
+//! Heartbeat log formats, and reading logs back.
+
+use clock::Clock;
+use hbs::HeartbeatRecord;
+use hbs_acc::HeartbeatAccRecord;
+use hbs_pow::HeartbeatPowRecord;
+use hbs_acc_pow::HeartbeatAccPowRecord;
+
+pub mod analyze;
+mod background;
+pub mod binary;
+pub mod diff;
+mod format;
+pub mod json;
+mod metadata;
+pub mod read;
+mod replay;
+mod rotate;
+
+pub use self::background::{BackgroundLog, Backpressure};
+pub use self::format::LogFormat;
+pub(crate) use self::format::{record_from_values, visit_values, Value};
+pub use self::metadata::LogMetadata;
+pub use self::replay::{Replay, Replayed};
+pub use self::rotate::{RotatingFile, Rotation};
+
+/// Log columns common to all heartbeat variants.
+pub const PERF_COLUMNS: [&str; 12] = ["HB", "Tag",
+                                      "Global_Work", "Window_Work", "Work",
+                                      "Global_Time", "Window_Time", "Start_Time", "End_Time",
+                                      "Global_Perf", "Window_Perf", "Instant_Perf"];
+
+/// Log columns for accuracy, following the common columns.
+pub const ACC_COLUMNS: [&str; 6] = ["Global_Acc", "Window_Acc", "Acc",
+                                    "Global_Acc_Rate", "Window_Acc_Rate", "Instant_Acc_Rate"];
+
+/// Log columns for power, following the common and accuracy columns.
+pub const POW_COLUMNS: [&str; 7] = ["Global_Energy", "Window_Energy", "Start_Energy",
+                                    "End_Energy", "Global_Pwr", "Window_Pwr", "Instant_Pwr"];
+
+/// The heartbeat type that wrote a log.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum LogVariant {
+    /// `Heartbeat`
+    Heartbeat,
+    /// `HeartbeatAcc`
+    Acc,
+    /// `HeartbeatPow`
+    Pow,
+    /// `HeartbeatAccPow`
+    AccPow,
+}
+
+impl LogVariant {
+    pub const ALL: [LogVariant; 4] =
+        [LogVariant::Heartbeat, LogVariant::Acc, LogVariant::Pow, LogVariant::AccPow];
+
+    /// The name of the heartbeat type.
+    pub fn name(self) -> &'static str {
+        match self {
+            LogVariant::Heartbeat => "Heartbeat",
+            LogVariant::Acc => "HeartbeatAcc",
+            LogVariant::Pow => "HeartbeatPow",
+            LogVariant::AccPow => "HeartbeatAccPow",
+        }
+    }
+
+    /// Get the variant of the heartbeat type with the given `name`.
+    pub fn from_name(name: &str) -> Option<LogVariant> {
+        LogVariant::ALL.iter().cloned().find(|v| v.name() == name)
+    }
+
+    /// Whether records have accuracy data.
+    pub fn has_accuracy(self) -> bool {
+        self == LogVariant::Acc || self == LogVariant::AccPow
+    }
+
+    /// Whether records have power data.
+    pub fn has_power(self) -> bool {
+        self == LogVariant::Pow || self == LogVariant::AccPow
+    }
+
+    /// The names of the log columns, in order.
+    pub fn columns(self) -> Vec<&'static str> {
+        let mut columns = PERF_COLUMNS.to_vec();
+        if self.has_accuracy() {
+            columns.extend_from_slice(&ACC_COLUMNS);
+        }
+        if self.has_power() {
+            columns.extend_from_slice(&POW_COLUMNS);
+        }
+        columns
+    }
+}
+
+/// Options for a heartbeat's log, used with `with_log_options` on the heartbeat types.
+#[derive(Default)]
+pub struct LogOptions {
+    format: LogFormat,
+    metadata: bool,
+    clock: Option<Box<dyn Clock + Send>>,
+}
+
+impl LogOptions {
+    /// Fixed-width logs without metadata, timestamped by `MonotonicClock`.
+    pub fn new() -> LogOptions {
+        LogOptions::default()
+    }
+
+    /// Write the log in `format`.
+    pub fn format(mut self, format: LogFormat) -> LogOptions {
+        self.format = format;
+        self
+    }
+
+    /// Start the log with a `LogMetadata` preamble describing the run.
+    pub fn metadata(mut self, metadata: bool) -> LogOptions {
+        self.metadata = metadata;
+        self
+    }
+
+    /// Timestamp heartbeats with `clock`, which is named in the metadata.
+    ///
+    /// Equivalent to calling `set_clock` on the heartbeat, which would leave the metadata stale.
+    pub fn clock<C: Clock + Send + 'static>(mut self, clock: C) -> LogOptions {
+        self.clock = Some(Box::new(clock));
+        self
+    }
+
+    /// Split into the format, clock, and metadata (if enabled) for a new heartbeat.
+    pub(crate) fn open(self,
+                       variant: LogVariant,
+                       window_size: usize,
+                       default_clock: Box<dyn Clock + Send>)
+        -> (LogFormat, Box<dyn Clock + Send>, Option<LogMetadata>) {
+        let clock = self.clock.unwrap_or(default_clock);
+        let metadata = if self.metadata {
+            Some(LogMetadata::for_heartbeat(variant, window_size, clock.name()))
+        } else {
+            None
+        };
+        (self.format, clock, metadata)
+    }
+}
+
+/// Accuracy data from a `HeartbeatAcc` or `HeartbeatAccPow` log record.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct LogAccuracy {
+    pub global_accuracy: u64,
+    pub window_accuracy: u64,
+    pub accuracy: u64,
+    pub global_accuracy_rate: f64,
+    pub window_accuracy_rate: f64,
+    pub instant_accuracy_rate: f64,
+}
+
+/// Power data from a `HeartbeatPow` or `HeartbeatAccPow` log record.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct LogPower {
+    pub global_energy: u64,
+    pub window_energy: u64,
+    pub start_energy: u64,
+    pub end_energy: u64,
+    pub global_power: f64,
+    pub window_power: f64,
+    pub instant_power: f64,
+}
+
+/// A heartbeat record from a log of any variant.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct LogRecord {
+    pub id: u64,
+    pub user_tag: u64,
+    pub global_work: u64,
+    pub window_work: u64,
+    pub work: u64,
+    pub global_time: u64,
+    pub window_time: u64,
+    pub start_time: u64,
+    pub end_time: u64,
+    pub global_perf: f64,
+    pub window_perf: f64,
+    pub instant_perf: f64,
+    pub accuracy: Option<LogAccuracy>,
+    pub power: Option<LogPower>,
+}
+
+impl LogRecord {
+    /// The variant of heartbeat that could have written this record.
+    pub fn variant(&self) -> LogVariant {
+        match (self.accuracy.is_some(), self.power.is_some()) {
+            (false, false) => LogVariant::Heartbeat,
+            (true, false) => LogVariant::Acc,
+            (false, true) => LogVariant::Pow,
+            (true, true) => LogVariant::AccPow,
+        }
+    }
+}
+
+impl From<&HeartbeatRecord> for LogRecord {
+    fn from(r: &HeartbeatRecord) -> LogRecord {
+        LogRecord {
+            id: r.id,
+            user_tag: r.user_tag,
+            global_work: r.wd.global,
+            window_work: r.wd.window,
+            work: r.work,
+            global_time: r.td.global,
+            window_time: r.td.window,
+            start_time: r.start_time,
+            end_time: r.end_time,
+            global_perf: r.perf.global,
+            window_perf: r.perf.window,
+            instant_perf: r.perf.instant,
+            accuracy: None,
+            power: None,
+        }
+    }
+}
+
+impl From<&HeartbeatAccRecord> for LogRecord {
+    fn from(r: &HeartbeatAccRecord) -> LogRecord {
+        LogRecord {
+            id: r.id,
+            user_tag: r.user_tag,
+            global_work: r.wd.global,
+            window_work: r.wd.window,
+            work: r.work,
+            global_time: r.td.global,
+            window_time: r.td.window,
+            start_time: r.start_time,
+            end_time: r.end_time,
+            global_perf: r.perf.global,
+            window_perf: r.perf.window,
+            instant_perf: r.perf.instant,
+            accuracy: Some(LogAccuracy {
+                global_accuracy: r.ad.global,
+                window_accuracy: r.ad.window,
+                accuracy: r.accuracy,
+                global_accuracy_rate: r.acc.global,
+                window_accuracy_rate: r.acc.window,
+                instant_accuracy_rate: r.acc.instant,
+            }),
+            power: None,
+        }
+    }
+}
+
+impl From<&HeartbeatPowRecord> for LogRecord {
+    fn from(r: &HeartbeatPowRecord) -> LogRecord {
+        LogRecord {
+            id: r.id,
+            user_tag: r.user_tag,
+            global_work: r.wd.global,
+            window_work: r.wd.window,
+            work: r.work,
+            global_time: r.td.global,
+            window_time: r.td.window,
+            start_time: r.start_time,
+            end_time: r.end_time,
+            global_perf: r.perf.global,
+            window_perf: r.perf.window,
+            instant_perf: r.perf.instant,
+            accuracy: None,
+            power: Some(LogPower {
+                global_energy: r.ed.global,
+                window_energy: r.ed.window,
+                start_energy: r.start_energy,
+                end_energy: r.end_energy,
+                global_power: r.pwr.global,
+                window_power: r.pwr.window,
+                instant_power: r.pwr.instant,
+            }),
+        }
+    }
+}
+
+impl From<&HeartbeatAccPowRecord> for LogRecord {
+    fn from(r: &HeartbeatAccPowRecord) -> LogRecord {
+        LogRecord {
+            id: r.id,
+            user_tag: r.user_tag,
+            global_work: r.wd.global,
+            window_work: r.wd.window,
+            work: r.work,
+            global_time: r.td.global,
+            window_time: r.td.window,
+            start_time: r.start_time,
+            end_time: r.end_time,
+            global_perf: r.perf.global,
+            window_perf: r.perf.window,
+            instant_perf: r.perf.instant,
+            accuracy: Some(LogAccuracy {
+                global_accuracy: r.ad.global,
+                window_accuracy: r.ad.window,
+                accuracy: r.accuracy,
+                global_accuracy_rate: r.acc.global,
+                window_accuracy_rate: r.acc.window,
+                instant_accuracy_rate: r.acc.instant,
+            }),
+            power: Some(LogPower {
+                global_energy: r.ed.global,
+                window_energy: r.ed.window,
+                start_energy: r.start_energy,
+                end_energy: r.end_energy,
+                global_power: r.pwr.global,
+                window_power: r.pwr.window,
+                instant_power: r.pwr.instant,
+            }),
+        }
+    }
+}
@@ -0,0 +1,533 @@
+//! Parse the fixed-width and CSV text logs written by all heartbeat variants, or logs in any
+//! `LogFormat`.
+
+use std::error::Error;
+use std::fmt;
+use std::fs::File;
+use std::io::{self, BufRead, BufReader, Chain, Cursor, Read};
+use std::path::Path;
+use std::str::FromStr;
+use super::binary::{BinaryLogReader, MAGIC};
+use super::json::JsonLogReader;
+use super::{LogAccuracy, LogFormat, LogMetadata, LogPower, LogRecord, LogVariant};
+
+/// Errors reported while reading a log.
+#[derive(Debug)]
+pub enum ReadError {
+    /// An I/O error while reading the log.
+    Io(io::Error),
+    /// The log is empty, with no header row.
+    MissingHeader,
+    /// The header row doesn't match any heartbeat variant's columns.
+    UnknownHeader(String),
+    /// A record couldn't be parsed, with the 1-based line (or binary record) number and a
+    /// description.
+    Malformed { line: usize, msg: String },
+}
+
+impl fmt::Display for ReadError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            ReadError::Io(ref e) => write!(f, "Log I/O error: {}", e),
+            ReadError::MissingHeader => write!(f, "Log has no header row"),
+            ReadError::UnknownHeader(ref h) => write!(f, "Unrecognized log header: {}", h),
+            ReadError::Malformed { line, ref msg } => {
+                write!(f, "Malformed log record on line {}: {}", line, msg)
+            }
+        }
+    }
+}
+
+impl Error for ReadError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match *self {
+            ReadError::Io(ref e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+impl From<io::Error> for ReadError {
+    fn from(e: io::Error) -> ReadError {
+        ReadError::Io(e)
+    }
+}
+
+/// Parses the fields of one record, tracking the current column for error messages.
+struct Fields<I> {
+    fields: I,
+    columns: Vec<&'static str>,
+    index: usize,
+    line: usize,
+}
+
+impl<'a, I: Iterator<Item = &'a str>> Fields<I> {
+    fn next<T: FromStr>(&mut self) -> Result<T, ReadError>
+        where T::Err: fmt::Display {
+        let column = self.columns[self.index];
+        self.index += 1;
+        let field = self.fields.next().ok_or_else(|| ReadError::Malformed {
+            line: self.line,
+            msg: format!("missing column {}", column),
+        })?;
+        field.parse().map_err(|e| ReadError::Malformed {
+            line: self.line,
+            msg: format!("invalid {} value '{}': {}", column, field, e),
+        })
+    }
+}
+
+/// Parse a record from a line of a fixed-width log written by `variant`, numbered `line` for
+/// errors.
+pub fn parse_record(variant: LogVariant, text: &str, line: usize) -> Result<LogRecord, ReadError> {
+    parse_fields(variant, text.split_whitespace(), line)
+}
+
+/// Parse a record from a line of a CSV log written by `variant`, numbered `line` for errors.
+pub fn parse_csv_record(variant: LogVariant,
+                        text: &str,
+                        line: usize) -> Result<LogRecord, ReadError> {
+    parse_fields(variant, text.split(',').map(str::trim), line)
+}
+
+/// Parse a record from its fields in column order.
+pub(crate) fn parse_fields<'a, I>(variant: LogVariant,
+                                  fields: I,
+                                  line: usize) -> Result<LogRecord, ReadError>
+    where I: Iterator<Item = &'a str> + Clone {
+    let columns = variant.columns();
+    let count = fields.clone().count();
+    if count > columns.len() {
+        return Err(ReadError::Malformed {
+            line,
+            msg: format!("expected {} columns, found {}", columns.len(), count),
+        });
+    }
+    let mut f = Fields { fields, columns, index: 0, line };
+    let mut r = LogRecord {
+        id: f.next()?,
+        user_tag: f.next()?,
+        global_work: f.next()?,
+        window_work: f.next()?,
+        work: f.next()?,
+        global_time: f.next()?,
+        window_time: f.next()?,
+        start_time: f.next()?,
+        end_time: f.next()?,
+        global_perf: f.next()?,
+        window_perf: f.next()?,
+        instant_perf: f.next()?,
+        accuracy: None,
+        power: None,
+    };
+    if variant.has_accuracy() {
+        r.accuracy = Some(LogAccuracy {
+            global_accuracy: f.next()?,
+            window_accuracy: f.next()?,
+            accuracy: f.next()?,
+            global_accuracy_rate: f.next()?,
+            window_accuracy_rate: f.next()?,
+            instant_accuracy_rate: f.next()?,
+        });
+    }
+    if variant.has_power() {
+        r.power = Some(LogPower {
+            global_energy: f.next()?,
+            window_energy: f.next()?,
+            start_energy: f.next()?,
+            end_energy: f.next()?,
+            global_power: f.next()?,
+            window_power: f.next()?,
+            instant_power: f.next()?,
+        });
+    }
+    Ok(r)
+}
+
+/// Detect the heartbeat variant that wrote a fixed-width or CSV log from its header row.
+pub fn parse_header(text: &str) -> Result<LogVariant, ReadError> {
+    parse_text_header(text).map(|(variant, _)| variant)
+}
+
+/// Detect the variant and format of a text log from its header row.
+fn parse_text_header(text: &str) -> Result<(LogVariant, LogFormat), ReadError> {
+    let (names, format): (Vec<&str>, _) = if text.contains(',') {
+        (text.split(',').map(str::trim).collect(), LogFormat::Csv)
+    } else {
+        (text.split_whitespace().collect(), LogFormat::FixedWidth)
+    };
+    LogVariant::ALL.iter()
+        .cloned()
+        .find(|v| v.columns() == names)
+        .map(|v| (v, format))
+        .ok_or_else(|| ReadError::UnknownHeader(names.join(" ")))
+}
+
+/// Iterates over the records of a fixed-width or CSV log, detecting its variant and format from
+/// the header row.
+///
+/// `# key: value` comment lines before the header are read as metadata.
+/// Blank lines are skipped.
+pub struct LogReader<R> {
+    lines: io::Lines<R>,
+    line: usize,
+    variant: LogVariant,
+    format: LogFormat,
+    metadata: LogMetadata,
+}
+
+impl LogReader<BufReader<File>> {
+    /// Open the log file at `path`.
+    pub fn open<P: AsRef<Path>>(path: P) -> Result<LogReader<BufReader<File>>, ReadError> {
+        LogReader::new(BufReader::new(File::open(path)?))
+    }
+}
+
+impl<R: BufRead> LogReader<R> {
+    /// Read the header row from `reader`.
+    pub fn new(reader: R) -> Result<LogReader<R>, ReadError> {
+        let mut lines = reader.lines();
+        let mut line = 0;
+        let mut metadata = LogMetadata::default();
+        loop {
+            line += 1;
+            let text = match lines.next() {
+                Some(text) => text?,
+                None => return Err(ReadError::MissingHeader),
+            };
+            let text = text.trim();
+            if let Some(comment) = text.strip_prefix('#') {
+                metadata.parse_entry(comment);
+            } else if !text.is_empty() {
+                let (variant, format) = parse_text_header(text)?;
+                return Ok(LogReader { lines, line, variant, format, metadata });
+            }
+        }
+    }
+
+    /// The heartbeat variant that wrote the log.
+    pub fn variant(&self) -> LogVariant {
+        self.variant
+    }
+
+    /// The format of the log, `FixedWidth` or `Csv`.
+    pub fn format(&self) -> LogFormat {
+        self.format
+    }
+
+    /// The log's metadata, which is empty if it has none.
+    pub fn metadata(&self) -> &LogMetadata {
+        &self.metadata
+    }
+}
+
+impl<R: BufRead> Iterator for LogReader<R> {
+    type Item = Result<LogRecord, ReadError>;
+
+    fn next(&mut self) -> Option<Result<LogRecord, ReadError>> {
+        loop {
+            self.line += 1;
+            match self.lines.next()? {
+                Ok(ref text) if text.trim().is_empty() => continue,
+                Ok(text) if self.format == LogFormat::Csv => {
+                    return Some(parse_csv_record(self.variant, &text, self.line));
+                }
+                Ok(text) => return Some(parse_record(self.variant, &text, self.line)),
+                Err(e) => return Some(Err(e.into())),
+            }
+        }
+    }
+}
+
+/// A reader with the bytes read to detect its format put back in front.
+pub type Sniffed<R> = Chain<Cursor<Vec<u8>>, R>;
+
+/// Reads a log in any `LogFormat`, detecting the format from its first bytes.
+pub enum AnyLogReader<R> {
+    Text(LogReader<Sniffed<R>>),
+    Json(JsonLogReader<Sniffed<R>>),
+    Binary(BinaryLogReader<Sniffed<R>>),
+}
+
+/// Whether `prefix` is enough to tell binary logs from JSON Lines and text logs.
+fn detected(prefix: &[u8]) -> bool {
+    if prefix.len() < MAGIC.len() && MAGIC.starts_with(prefix) {
+        return false;
+    }
+    prefix.starts_with(&MAGIC) || prefix.iter().any(|b| !b.is_ascii_whitespace())
+}
+
+impl AnyLogReader<BufReader<File>> {
+    /// Open the log file at `path`.
+    pub fn open<P: AsRef<Path>>(path: P) -> Result<AnyLogReader<BufReader<File>>, ReadError> {
+        AnyLogReader::new(BufReader::new(File::open(path)?))
+    }
+}
+
+impl<R: BufRead> AnyLogReader<R> {
+    /// Read the header from `reader`.
+    pub fn new(mut reader: R) -> Result<AnyLogReader<R>, ReadError> {
+        // reads may be short, e.g. from a pipe
+        let mut prefix = Vec::new();
+        while !detected(&prefix) {
+            let n = {
+                let buf = reader.fill_buf()?;
+                prefix.extend_from_slice(buf);
+                buf.len()
+            };
+            if n == 0 {
+                break;
+            }
+            reader.consume(n);
+        }
+        let first = prefix.iter().find(|b| !b.is_ascii_whitespace()).cloned();
+        let binary = prefix.starts_with(&MAGIC);
+        let reader = Cursor::new(prefix).chain(reader);
+        if binary {
+            Ok(AnyLogReader::Binary(BinaryLogReader::new(reader)?))
+        } else if first == Some(b'{') {
+            Ok(AnyLogReader::Json(JsonLogReader::new(reader)?))
+        } else {
+            Ok(AnyLogReader::Text(LogReader::new(reader)?))
+        }
+    }
+
+    /// The heartbeat variant that wrote the log.
+    pub fn variant(&self) -> LogVariant {
+        match *self {
+            AnyLogReader::Text(ref r) => r.variant(),
+            AnyLogReader::Json(ref r) => r.variant(),
+            AnyLogReader::Binary(ref r) => r.variant(),
+        }
+    }
+
+    /// The format of the log.
+    pub fn format(&self) -> LogFormat {
+        match *self {
+            AnyLogReader::Text(ref r) => r.format(),
+            AnyLogReader::Json(_) => LogFormat::JsonLines,
+            AnyLogReader::Binary(_) => LogFormat::Binary,
+        }
+    }
+
+    /// The log's metadata, which is empty if it has none.
+    pub fn metadata(&self) -> &LogMetadata {
+        match *self {
+            AnyLogReader::Text(ref r) => r.metadata(),
+            AnyLogReader::Json(ref r) => r.metadata(),
+            AnyLogReader::Binary(ref r) => r.metadata(),
+        }
+    }
+}
+
+impl<R: BufRead> Iterator for AnyLogReader<R> {
+    type Item = Result<LogRecord, ReadError>;
+
+    fn next(&mut self) -> Option<Result<LogRecord, ReadError>> {
+        match *self {
+            AnyLogReader::Text(ref mut r) => r.next(),
+            AnyLogReader::Json(ref mut r) => r.next(),
+            AnyLogReader::Binary(ref mut r) => r.next(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use hbs::Heartbeat;
+    use hbs_acc::HeartbeatAcc;
+    use hbs_pow::HeartbeatPow;
+    use hbs_acc_pow::HeartbeatAccPow;
+    use hbs_common::TestLog;
+    use clock::TestClock;
+    use log::{LogFormat, LogOptions};
+
+    fn read(log: &TestLog) -> (LogVariant, Vec<LogRecord>) {
+        let contents = log.contents();
+        let reader = LogReader::new(contents.as_bytes()).unwrap();
+        let variant = reader.variant();
+        (variant, reader.collect::<Result<_, _>>().unwrap())
+    }
+
+    /// Logs round floats to 6 decimal places.
+    fn assert_close(a: f64, b: f64) {
+        assert!((a - b).abs() <= 5e-7, "{} != {}", a, b);
+    }
+
+    fn assert_logged(logged: &LogRecord, r: &LogRecord) {
+        assert_eq!((logged.id, logged.user_tag, logged.work, logged.window_work),
+                   (r.id, r.user_tag, r.work, r.window_work));
+        assert_eq!((logged.global_time, logged.start_time, logged.end_time),
+                   (r.global_time, r.start_time, r.end_time));
+        assert_close(logged.window_perf, r.window_perf);
+        match (logged.accuracy, r.accuracy) {
+            (Some(la), Some(ra)) => {
+                assert_eq!((la.accuracy, la.global_accuracy), (ra.accuracy, ra.global_accuracy));
+                assert_close(la.instant_accuracy_rate, ra.instant_accuracy_rate);
+            }
+            (None, None) => (),
+            _ => panic!("Accuracy mismatch"),
+        }
+        match (logged.power, r.power) {
+            (Some(lp), Some(rp)) => {
+                assert_eq!((lp.start_energy, lp.end_energy, lp.window_energy),
+                           (rp.start_energy, rp.end_energy, rp.window_energy));
+                assert_close(lp.window_power, rp.window_power);
+            }
+            (None, None) => (),
+            _ => panic!("Power mismatch"),
+        }
+    }
+
+    #[test]
+    fn test_read_heartbeat() {
+        let log = TestLog::default();
+        let mut hb = Heartbeat::new(10, None, log.sink()).unwrap();
+        for tag in 0..3 {
+            hb.heartbeat(tag, tag + 1, tag * 1000, (tag + 1) * 1000 + 7);
+        }
+        hb.log_to_buffer_index().unwrap();
+        let (variant, records) = read(&log);
+        assert_eq!(variant, LogVariant::Heartbeat);
+        assert_eq!(records.len(), 3);
+        for (logged, r) in records.iter().zip(hb.records()) {
+            assert_logged(logged, &LogRecord::from(r));
+        }
+    }
+
+    #[test]
+    fn test_read_acc() {
+        let log = TestLog::default();
+        let mut hb = HeartbeatAcc::new(10, None, log.sink()).unwrap();
+        for tag in 0..3 {
+            hb.heartbeat(tag, 1, tag * 1000, (tag + 1) * 1000, tag * 3);
+        }
+        hb.log_to_buffer_index().unwrap();
+        let (variant, records) = read(&log);
+        assert_eq!(variant, LogVariant::Acc);
+        for (logged, r) in records.iter().zip(hb.records()) {
+            assert_logged(logged, &LogRecord::from(r));
+        }
+    }
+
+    #[test]
+    fn test_read_pow() {
+        let log = TestLog::default();
+        let mut hb = HeartbeatPow::new(10, None, log.sink()).unwrap();
+        for tag in 0..3 {
+            hb.heartbeat(tag, 1, tag * 1000, (tag + 1) * 1000, tag * 500, (tag + 1) * 500);
+        }
+        hb.log_to_buffer_index().unwrap();
+        let (variant, records) = read(&log);
+        assert_eq!(variant, LogVariant::Pow);
+        for (logged, r) in records.iter().zip(hb.records()) {
+            assert_logged(logged, &LogRecord::from(r));
+        }
+    }
+
+    #[test]
+    fn test_read_acc_pow() {
+        let log = TestLog::default();
+        let mut hb = HeartbeatAccPow::new(10, None, log.sink()).unwrap();
+        for tag in 0..3 {
+            hb.heartbeat(tag, 1, tag * 1000, (tag + 1) * 1000, 2, tag * 500, (tag + 1) * 500);
+        }
+        hb.log_to_buffer_index().unwrap();
+        let (variant, records) = read(&log);
+        assert_eq!(variant, LogVariant::AccPow);
+        assert_eq!(records.len(), 3);
+        for (logged, r) in records.iter().zip(hb.records()) {
+            assert_logged(logged, &LogRecord::from(r));
+        }
+    }
+
+    #[test]
+    fn test_metadata() {
+        let log = TestLog::default();
+        let options = LogOptions::new().metadata(true).clock(TestClock::default());
+        let mut hb = HeartbeatPow::with_log_options(7, None, log.sink(), options).unwrap();
+        hb.heartbeat(0, 1, 0, 1000, 0, 10);
+        hb.log_to_buffer_index().unwrap();
+        assert!(log.contents().starts_with("# variant: HeartbeatPow\n# window_size: 7\n"));
+        let contents = log.contents();
+        let reader = LogReader::new(contents.as_bytes()).unwrap();
+        let meta = reader.metadata();
+        assert_eq!(meta.variant(), Some(LogVariant::Pow));
+        assert_eq!(meta.window_size(), Some(7));
+        assert_eq!(meta.clock(), Some("test"));
+        assert_eq!(meta.energy_units(), Some("uJ"));
+        assert!(meta.start_time().is_some());
+        assert_eq!(reader.count(), 1);
+    }
+
+    #[test]
+    fn test_any_format() {
+        let formats = [LogFormat::FixedWidth, LogFormat::Csv, LogFormat::JsonLines,
+                       LogFormat::Binary];
+        for (format, metadata) in formats.iter().flat_map(|&f| vec![(f, false), (f, true)]) {
+            let log = TestLog::default();
+            let options = LogOptions::new().format(format).metadata(metadata);
+            let mut hb = HeartbeatAccPow::with_log_options(4, None, log.sink(), options).unwrap();
+            for tag in 0..3 {
+                hb.heartbeat(tag, 1, tag * 1000, (tag + 1) * 1000 + 7, 2, tag * 10, tag * 10 + 3);
+            }
+            hb.log_to_buffer_index().unwrap();
+            let bytes = log.bytes();
+            let reader = AnyLogReader::new(&bytes[..]).unwrap();
+            assert_eq!(reader.format(), format);
+            assert_eq!(reader.variant(), LogVariant::AccPow);
+            let window_size = if metadata { Some(4) } else { None };
+            assert_eq!(reader.metadata().window_size(), window_size);
+            let records: Vec<LogRecord> = reader.collect::<Result<_, _>>().unwrap();
+            assert_eq!(records.len(), 3);
+            for (logged, r) in records.iter().zip(hb.records()) {
+                assert_logged(logged, &LogRecord::from(r));
+            }
+            // reads of one byte at a time, like a slow pipe
+            let slow = AnyLogReader::new(BufReader::with_capacity(1, &bytes[..])).unwrap();
+            assert_eq!(slow.format(), format);
+            let slow: Vec<LogRecord> = slow.collect::<Result<_, _>>().unwrap();
+            assert_eq!(slow, records);
+        }
+    }
+
+    #[test]
+    fn test_csv() {
+        let header = LogVariant::Heartbeat.columns().join(",");
+        let log = format!("{}\n0,0,1,1,1,10,10,0,10,0.1,0.1,0.1\n1,0,2,x\n", header);
+        let reader = LogReader::new(log.as_bytes()).unwrap();
+        assert_eq!((reader.variant(), reader.format()), (LogVariant::Heartbeat, LogFormat::Csv));
+        let results: Vec<_> = reader.collect();
+        assert_eq!(results[0].as_ref().unwrap().instant_perf, 0.1);
+        assert_eq!(results[1].as_ref().unwrap_err().to_string(),
+                   "Malformed log record on line 3: invalid Window_Work value 'x': \
+                    invalid digit found in string");
+    }
+
+    #[test]
+    fn test_malformed() {
+        match LogReader::new("".as_bytes()) {
+            Err(ReadError::MissingHeader) => (),
+            _ => panic!("Expected MissingHeader"),
+        }
+        match LogReader::new("HB Tag Foo\n".as_bytes()) {
+            Err(ReadError::UnknownHeader(h)) => assert_eq!(h, "HB Tag Foo"),
+            _ => panic!("Expected UnknownHeader"),
+        }
+        let header = LogVariant::Heartbeat.columns().join(" ");
+        let log = format!("{}\n0 0 1 1 1 10 10 0 10 1.0 1.0 1.0\n\n1 0 2 x\n1 0\n", header);
+        let results: Vec<_> = LogReader::new(log.as_bytes()).unwrap().collect();
+        assert!(results[0].is_ok());
+        assert_eq!(results[1].as_ref().unwrap_err().to_string(),
+                   "Malformed log record on line 4: invalid Window_Work value 'x': \
+                    invalid digit found in string");
+        assert_eq!(results[2].as_ref().unwrap_err().to_string(),
+                   "Malformed log record on line 5: missing column Global_Work");
+        let extra = format!("{}\n0 0 1 1 1 10 10 0 10 1.0 1.0 1.0 5\n", header);
+        match LogReader::new(extra.as_bytes()).unwrap().next() {
+            Some(Err(ReadError::Malformed { line: 2, .. })) => (),
+            _ => panic!("Expected Malformed"),
+        }
+    }
+}
@@ -0,0 +1,206 @@
+use error::HeartbeatError;
+use hbs::Heartbeat;
+use hbs_acc::HeartbeatAcc;
+use hbs_pow::HeartbeatPow;
+use hbs_acc_pow::HeartbeatAccPow;
+use hbs_common::LogSink;
+use super::{LogOptions, LogRecord, LogVariant};
+
+enum Target {
+    Heartbeat(Heartbeat),
+    Acc(HeartbeatAcc),
+    Pow(HeartbeatPow),
+    AccPow(HeartbeatAccPow),
+}
+
+/// Re-feeds logged heartbeats into a new heartbeat, e.g. to see what the window statistics
+/// would have been with a different window size.
+///
+/// ```no_run
+/// # use heartbeats_simple::log::{LogOptions, Replay};
+/// # use heartbeats_simple::log::read::LogReader;
+/// # use std::fs::File;
+/// let reader = LogReader::open("heartbeat.log").unwrap();
+/// let log = File::create("heartbeat-50.log").unwrap();
+/// let mut replay = Replay::new(reader.variant(), 50, Some(Box::new(log)), LogOptions::new())
+///     .unwrap();
+/// for record in replay.records(reader) {
+///     println!("{}", record.unwrap().window_perf);
+/// }
+/// replay.finish().unwrap();
+/// ```
+pub struct Replay {
+    target: Target,
+}
+
+impl Replay {
+    /// Replay into a new heartbeat of `variant` with `window_size`, which writes `log` (if any)
+    /// according to `options`.
+    pub fn new(variant: LogVariant,
+               window_size: usize,
+               log: Option<LogSink>,
+               options: LogOptions) -> Result<Replay, HeartbeatError> {
+        let target = match variant {
+            LogVariant::Heartbeat => {
+                Target::Heartbeat(Heartbeat::with_log_options(window_size, None, log, options)?)
+            }
+            LogVariant::Acc => {
+                Target::Acc(HeartbeatAcc::with_log_options(window_size, None, log, options)?)
+            }
+            LogVariant::Pow => {
+                Target::Pow(HeartbeatPow::with_log_options(window_size, None, log, options)?)
+            }
+            LogVariant::AccPow => {
+                Target::AccPow(HeartbeatAccPow::with_log_options(window_size, None, log, options)?)
+            }
+        };
+        Ok(Replay { target })
+    }
+
+    /// The variant of the new heartbeat.
+    pub fn variant(&self) -> LogVariant {
+        match self.target {
+            Target::Heartbeat(_) => LogVariant::Heartbeat,
+            Target::Acc(_) => LogVariant::Acc,
+            Target::Pow(_) => LogVariant::Pow,
+            Target::AccPow(_) => LogVariant::AccPow,
+        }
+    }
+
+    /// Issue a heartbeat with the tag, work, and times of `record`, and its accuracy and energy
+    /// if the new heartbeat has them (0 if the record doesn't).
+    ///
+    /// Returns the record as computed by the new heartbeat.
+    pub fn heartbeat(&mut self, record: &LogRecord) -> LogRecord {
+        let r = record;
+        let accuracy = r.accuracy.map_or(0, |a| a.accuracy);
+        let (start_energy, end_energy) = r.power.map_or((0, 0), |p| (p.start_energy, p.end_energy));
+        match self.target {
+            Target::Heartbeat(ref mut hb) => {
+                hb.heartbeat(r.user_tag, r.work, r.start_time, r.end_time);
+                LogRecord::from(hb.latest().unwrap())
+            }
+            Target::Acc(ref mut hb) => {
+                hb.heartbeat(r.user_tag, r.work, r.start_time, r.end_time, accuracy);
+                LogRecord::from(hb.latest().unwrap())
+            }
+            Target::Pow(ref mut hb) => {
+                hb.heartbeat(r.user_tag, r.work, r.start_time, r.end_time, start_energy,
+                             end_energy);
+                LogRecord::from(hb.latest().unwrap())
+            }
+            Target::AccPow(ref mut hb) => {
+                hb.heartbeat(r.user_tag, r.work, r.start_time, r.end_time, accuracy, start_energy,
+                             end_energy);
+                LogRecord::from(hb.latest().unwrap())
+            }
+        }
+    }
+
+    /// Replay each record from `records` (e.g. a `LogReader`) as it's iterated, yielding the
+    /// records computed by the new heartbeat, and passing errors through.
+    pub fn records<I, E>(&mut self, records: I) -> Replayed<'_, I::IntoIter>
+        where I: IntoIterator<Item = Result<LogRecord, E>>
+    {
+        Replayed { replay: self, records: records.into_iter() }
+    }
+
+    /// Write any records not yet logged and flush the log, reporting any logging errors.
+    pub fn finish(mut self) -> Result<(), HeartbeatError> {
+        match self.target {
+            Target::Heartbeat(ref mut hb) => hb.log_to_buffer_index(),
+            Target::Acc(ref mut hb) => hb.log_to_buffer_index(),
+            Target::Pow(ref mut hb) => hb.log_to_buffer_index(),
+            Target::AccPow(ref mut hb) => hb.log_to_buffer_index(),
+        }
+    }
+}
+
+/// An iterator over replayed records, created by `Replay::records`.
+pub struct Replayed<'a, I> {
+    replay: &'a mut Replay,
+    records: I,
+}
+
+impl<'a, I, E> Iterator for Replayed<'a, I> where I: Iterator<Item = Result<LogRecord, E>> {
+    type Item = Result<LogRecord, E>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let record = self.records.next()?;
+        Some(record.map(|r| self.replay.heartbeat(&r)))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use hbs_common::TestLog;
+    use log::read::{LogReader, ReadError};
+
+    /// Write a log of `variant` with `window_size`, returning its text and the records written.
+    fn write_log(variant: LogVariant, window_size: usize) -> (String, Vec<LogRecord>) {
+        let log = TestLog::default();
+        let mut replay = Replay::new(variant, window_size, Some(Box::new(log.clone())),
+                                     LogOptions::new()).unwrap();
+        let records = (0..10u64).map(|i| {
+            let mut r = LogRecord {
+                user_tag: i,
+                work: i % 3 + 1,
+                start_time: i * 1000,
+                end_time: i * 1000 + 500 + i * 50,
+                ..LogRecord::default()
+            };
+            r.accuracy = Some(Default::default());
+            r.accuracy.as_mut().unwrap().accuracy = i % 2;
+            r.power = Some(Default::default());
+            r.power.as_mut().unwrap().start_energy = i * 100;
+            r.power.as_mut().unwrap().end_energy = i * 100 + 70;
+            replay.heartbeat(&r)
+        }).collect();
+        replay.finish().unwrap();
+        (log.contents(), records)
+    }
+
+    #[test]
+    fn test_same_window() {
+        for &variant in LogVariant::ALL.iter() {
+            let (text, records) = write_log(variant, 4);
+            let reader = LogReader::new(text.as_bytes()).unwrap();
+            let log = TestLog::default();
+            let mut replay = Replay::new(reader.variant(), 4, Some(Box::new(log.clone())),
+                                         LogOptions::new()).unwrap();
+            assert_eq!(replay.variant(), variant);
+            let replayed: Vec<LogRecord> = replay.records(reader).map(|r| r.unwrap()).collect();
+            replay.finish().unwrap();
+            // the log rounds floating point values, which are then recomputed exactly
+            assert_eq!(replayed, records);
+            assert_eq!(log.contents(), text);
+        }
+    }
+
+    #[test]
+    fn test_window_size() {
+        let (text, records) = write_log(LogVariant::AccPow, 2);
+        let mut replay = Replay::new(LogVariant::AccPow, 5, None, LogOptions::new()).unwrap();
+        let reader = LogReader::new(text.as_bytes()).unwrap();
+        let replayed: Vec<LogRecord> = replay.records(reader).map(|r| r.unwrap()).collect();
+        // global statistics don't depend on the window
+        for (old, new) in records.iter().zip(replayed.iter()) {
+            assert_eq!(new.global_perf, old.global_perf);
+            assert_eq!(new.power.unwrap().global_energy, old.power.unwrap().global_energy);
+        }
+        let last = replayed.last().unwrap();
+        assert_eq!(last.window_work, records[5..].iter().map(|r| r.work).sum::<u64>());
+        assert_eq!(last.accuracy.unwrap().window_accuracy, 3);
+        assert_eq!(last.power.unwrap().window_energy, 5 * 70);
+    }
+
+    #[test]
+    fn test_errors() {
+        let mut replay = Replay::new(LogVariant::Heartbeat, 2, None, LogOptions::new()).unwrap();
+        let records = vec![Ok(LogRecord::default()), Err(ReadError::MissingHeader)];
+        let replayed: Vec<_> = replay.records(records).collect();
+        assert!(replayed[0].is_ok());
+        assert!(replayed[1].is_err());
+    }
+}
@@ -0,0 +1,316 @@
+use std::ffi::OsString;
+use std::fs::{self, File};
+use std::io::{self, BufWriter, Write};
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
+#[cfg(feature = "gzip")]
+use flate2::Compression;
+#[cfg(feature = "gzip")]
+use flate2::write::GzEncoder;
+
+/// When a `RotatingFile` rolls over, and which old files it keeps.
+#[derive(Debug, Clone, Default)]
+pub struct Rotation {
+    max_bytes: Option<u64>,
+    interval: Option<Duration>,
+    keep: usize,
+    #[cfg(feature = "gzip")]
+    gzip: bool,
+}
+
+impl Rotation {
+    /// Never roll over, keeping no old files.
+    pub fn new() -> Rotation {
+        Rotation::default()
+    }
+
+    /// Roll over before a write would make the file larger than `bytes`.
+    pub fn max_bytes(mut self, bytes: u64) -> Rotation {
+        self.max_bytes = Some(bytes);
+        self
+    }
+
+    /// Roll over on the first write after the file has been open for `interval`.
+    pub fn interval(mut self, interval: Duration) -> Rotation {
+        self.interval = Some(interval);
+        self
+    }
+
+    /// Keep `count` old files, named like `heartbeat.log.1` (newest) to `heartbeat.log.N`.
+    pub fn keep(mut self, count: usize) -> Rotation {
+        self.keep = count;
+        self
+    }
+
+    /// Compress old files with gzip, adding a `.gz` extension.
+    ///
+    /// Compression happens on the writing thread when the file rolls over, so consider a
+    /// background log (see `set_background_log` on the heartbeat types).
+    #[cfg(feature = "gzip")]
+    pub fn gzip(mut self, gzip: bool) -> Rotation {
+        self.gzip = gzip;
+        self
+    }
+
+    #[cfg(feature = "gzip")]
+    fn compress(&self) -> bool {
+        self.gzip
+    }
+
+    #[cfg(not(feature = "gzip"))]
+    fn compress(&self) -> bool {
+        false
+    }
+}
+
+/// A log sink that rolls over to a new file by size or age, keeping a number of old files.
+///
+/// A header given with `with_header` is repeated at the start of each new file. Heartbeats
+/// write each record with a single write, so records are never split across files.
+/// Don't wrap the sink in a `BufWriter`, which would merge writes; it's buffered internally.
+pub struct RotatingFile {
+    path: PathBuf,
+    rotation: Rotation,
+    file: BufWriter<File>,
+    size: u64,
+    opened: Instant,
+    header: Option<Vec<u8>>,
+}
+
+impl RotatingFile {
+    /// Create (or truncate) the log file at `path`.
+    pub fn create<P: AsRef<Path>>(path: P, rotation: Rotation) -> io::Result<RotatingFile> {
+        let path = path.as_ref().to_path_buf();
+        let file = BufWriter::new(File::create(&path)?);
+        Ok(RotatingFile { path, rotation, file, size: 0, opened: Instant::now(), header: None })
+    }
+
+    /// Repeat `header` at the start of each new file.
+    ///
+    /// The header isn't written to the current file, whose writer is expected to write it,
+    /// as heartbeats do. Build it with `LogFormat::write_header` (or
+    /// `write_header_with_metadata`) for the log's format and variant; JSON Lines logs without
+    /// metadata have no header.
+    pub fn with_header(mut self, header: Vec<u8>) -> RotatingFile {
+        self.header = if header.is_empty() { None } else { Some(header) };
+        self
+    }
+
+    /// The path of the current log file.
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// The path of the `n`th most recent old file.
+    pub fn rotated_path(&self, n: usize) -> PathBuf {
+        let mut name = OsString::from(self.path.as_os_str());
+        name.push(format!(".{}", n));
+        if self.rotation.compress() {
+            name.push(".gz");
+        }
+        PathBuf::from(name)
+    }
+
+    fn should_rotate(&self, len: usize) -> bool {
+        let header_len = self.header.as_ref().map_or(0, |h| h.len()) as u64;
+        // a file always gets at least one record, even if it's too big
+        if self.size <= header_len {
+            return false;
+        }
+        self.rotation.max_bytes.is_some_and(|max| self.size + len as u64 > max) ||
+            self.rotation.interval.is_some_and(|i| self.opened.elapsed() >= i)
+    }
+
+    fn rotate(&mut self) -> io::Result<()> {
+        self.file.flush()?;
+        let keep = self.rotation.keep;
+        if keep > 0 {
+            remove_if_exists(&self.rotated_path(keep))?;
+            for n in (1..keep).rev() {
+                rename_if_exists(&self.rotated_path(n), &self.rotated_path(n + 1))?;
+            }
+            self.archive()?;
+        }
+        self.file = BufWriter::new(File::create(&self.path)?);
+        self.opened = Instant::now();
+        self.size = 0;
+        if let Some(ref header) = self.header {
+            self.file.write_all(header)?;
+            self.size = header.len() as u64;
+        }
+        Ok(())
+    }
+
+    /// Move the current file to the most recent old file.
+    #[cfg(feature = "gzip")]
+    fn archive(&self) -> io::Result<()> {
+        if !self.rotation.gzip {
+            return fs::rename(&self.path, self.rotated_path(1));
+        }
+        let mut input = File::open(&self.path)?;
+        let output = File::create(self.rotated_path(1))?;
+        let mut encoder = GzEncoder::new(output, Compression::default());
+        io::copy(&mut input, &mut encoder)?;
+        encoder.finish()?;
+        fs::remove_file(&self.path)
+    }
+
+    #[cfg(not(feature = "gzip"))]
+    fn archive(&self) -> io::Result<()> {
+        fs::rename(&self.path, self.rotated_path(1))
+    }
+}
+
+fn remove_if_exists(path: &Path) -> io::Result<()> {
+    match fs::remove_file(path) {
+        Err(ref e) if e.kind() == io::ErrorKind::NotFound => Ok(()),
+        res => res,
+    }
+}
+
+fn rename_if_exists(from: &Path, to: &Path) -> io::Result<()> {
+    match fs::rename(from, to) {
+        Err(ref e) if e.kind() == io::ErrorKind::NotFound => Ok(()),
+        res => res,
+    }
+}
+
+impl Write for RotatingFile {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        if self.should_rotate(buf.len()) {
+            self.rotate()?;
+        }
+        self.file.write_all(buf)?;
+        self.size += buf.len() as u64;
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.file.flush()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use hbs_pow::HeartbeatPow;
+    use log::{LogFormat, LogOptions, LogVariant};
+    use log::read::LogReader;
+    use std::env;
+    use std::process;
+
+    fn temp_dir(name: &str) -> PathBuf {
+        let dir = env::temp_dir().join(format!("heartbeats-simple-rotate-{}-{}",
+                                               name, process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    #[test]
+    fn test_max_bytes() {
+        let dir = temp_dir("size");
+        let mut f = RotatingFile::create(dir.join("hb.log"), Rotation::new().max_bytes(25).keep(2))
+            .unwrap()
+            .with_header(b"header\n".to_vec());
+        f.write_all(b"header\n").unwrap();
+        for r in 0..7 {
+            f.write_all(format!("record {}\n", r).as_bytes()).unwrap();
+        }
+        f.flush().unwrap();
+        let read = |p: PathBuf| fs::read_to_string(p).unwrap();
+        assert_eq!(read(f.path().to_path_buf()), "header\nrecord 6\n");
+        assert_eq!(read(f.rotated_path(1)), "header\nrecord 4\nrecord 5\n");
+        assert_eq!(read(f.rotated_path(2)), "header\nrecord 2\nrecord 3\n");
+        assert!(!f.rotated_path(3).exists());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_interval() {
+        let dir = temp_dir("time");
+        let rotation = Rotation::new().interval(Duration::from_secs(0));
+        let mut f = RotatingFile::create(dir.join("hb.log"), rotation).unwrap()
+            .with_header(b"header\n".to_vec());
+        f.write_all(b"header\n").unwrap();
+        f.write_all(b"record 0\n").unwrap();
+        f.write_all(b"record 1\n").unwrap();
+        f.flush().unwrap();
+        // nothing is kept by default
+        assert_eq!(fs::read_to_string(f.path()).unwrap(), "header\nrecord 1\n");
+        assert!(!f.rotated_path(1).exists());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_heartbeat() {
+        let dir = temp_dir("heartbeat");
+        let path = dir.join("hb.log");
+        let mut header = Vec::new();
+        LogFormat::FixedWidth.write_header(LogVariant::Pow, &mut header).unwrap();
+        let f = RotatingFile::create(&path, Rotation::new().max_bytes(2000).keep(10)).unwrap()
+            .with_header(header);
+        let rotated: Vec<PathBuf> = (1..11).map(|n| f.rotated_path(n)).collect();
+        {
+            let mut hb = HeartbeatPow::new(4, None, Some(Box::new(f))).unwrap();
+            for tag in 0..20 {
+                hb.heartbeat(tag, 1, tag * 1000, (tag + 1) * 1000, tag * 10, (tag + 1) * 10);
+            }
+        }
+        let mut ids = Vec::new();
+        for p in rotated.iter().rev().filter(|p| p.exists()).chain(Some(&path)) {
+            let reader = LogReader::open(p).unwrap();
+            assert_eq!(reader.variant(), LogVariant::Pow);
+            ids.extend(reader.map(|r| r.unwrap().id));
+        }
+        assert!(rotated[0].exists());
+        assert_eq!(ids, (0..20).collect::<Vec<_>>());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_json_lines() {
+        // no header, so every file holds only records
+        let dir = temp_dir("jsonl");
+        let path = dir.join("hb.log");
+        let f = RotatingFile::create(&path, Rotation::new().max_bytes(1000).keep(10)).unwrap();
+        let rotated: Vec<PathBuf> = (1..11).map(|n| f.rotated_path(n)).collect();
+        {
+            let options = LogOptions::new().format(LogFormat::JsonLines);
+            let mut hb = HeartbeatPow::with_log_options(4, None, Some(Box::new(f)), options).unwrap();
+            for tag in 0..10 {
+                hb.heartbeat(tag, 1, tag * 1000, (tag + 1) * 1000, tag * 10, (tag + 1) * 10);
+            }
+        }
+        let mut ids = Vec::new();
+        for p in rotated.iter().rev().filter(|p| p.exists()).chain(Some(&path)) {
+            for line in fs::read_to_string(p).unwrap().lines() {
+                assert!(line.starts_with("{\"HB\":"), "{}", line);
+                ids.push(line[6..line.find(',').unwrap()].parse::<u64>().unwrap());
+            }
+        }
+        assert!(rotated[1].exists());
+        assert_eq!(ids, (0..10).collect::<Vec<_>>());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[cfg(feature = "gzip")]
+    #[test]
+    fn test_gzip() {
+        use flate2::read::GzDecoder;
+        use std::io::Read;
+
+        let dir = temp_dir("gzip");
+        let rotation = Rotation::new().max_bytes(10).keep(1).gzip(true);
+        let mut f = RotatingFile::create(dir.join("hb.log"), rotation).unwrap()
+            .with_header(b"header\n".to_vec());
+        f.write_all(b"header\n").unwrap();
+        f.write_all(b"record 0\n").unwrap();
+        f.write_all(b"record 1\n").unwrap();
+        assert!(f.rotated_path(1).to_string_lossy().ends_with("hb.log.1.gz"));
+        let mut text = String::new();
+        GzDecoder::new(File::open(f.rotated_path(1)).unwrap()).read_to_string(&mut text).unwrap();
+        assert_eq!(text, "header\nrecord 0\n");
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,421 @@
+//! Publish live heartbeat state to a memory-mapped file, for other local processes to observe.
+//!
+//! The file is a sequence of native-endian 64-bit words: an 8-word header, the latest record,
+//! then the window of recent records, oldest records overwritten first.
+//! Header word 0, as little-endian bytes, holds the magic bytes `HBSP`, the layout version as a
+//! `u16`, and the `LogVariant` as a `u8` (with the same codes as binary logs); the other words
+//! are the sequence number, window size, record count, publisher PID, and the number of words
+//! per record.
+//! Records hold their values in log column order (rates as `f64` bits), and record `id` is
+//! stored in window slot `id % window_size`.
+//!
+//! Updates are protected by a seqlock: the sequence number is odd while an update is in
+//! progress, so readers retry until they see the same even sequence number before and after
+//! reading.
+
+#[cfg(unix)]
+use libc;
+use std::ffi::OsString;
+use std::fs::{self, File, Metadata, OpenOptions};
+use std::io;
+#[cfg(unix)]
+use std::os::unix::fs::MetadataExt;
+#[cfg(unix)]
+use std::os::unix::io::AsRawFd;
+use std::path::{Path, PathBuf};
+use std::process;
+#[cfg(unix)]
+use std::ptr;
+use std::slice;
+use std::sync::atomic::{fence, AtomicU64, Ordering};
+use std::thread;
+use log::binary::{variant_code, variant_from_code};
+use log::{record_from_values, visit_values, LogRecord, LogVariant, Value};
+
+/// Identifies published heartbeat state.
+pub const PUBLISH_MAGIC: [u8; 4] = *b"HBSP";
+/// The layout version written by this crate.
+pub const PUBLISH_VERSION: u16 = 1;
+
+const HEADER_WORDS: usize = 8;
+/// Words per record, enough for `HeartbeatAccPow`.
+const RECORD_WORDS: usize = 25;
+
+const WORD_MAGIC: usize = 0;
+const WORD_SEQ: usize = 1;
+const WORD_WINDOW_SIZE: usize = 2;
+const WORD_COUNT: usize = 3;
+const WORD_PID: usize = 4;
+const WORD_RECORD_WORDS: usize = 5;
+
+/// How many times `PublishedReader::read` retries before giving up on a stuck publisher.
+const READ_ATTEMPTS: usize = 100000;
+
+fn magic_word(variant: LogVariant) -> u64 {
+    let mut bytes = [0u8; 8];
+    bytes[..4].copy_from_slice(&PUBLISH_MAGIC);
+    bytes[4..6].copy_from_slice(&PUBLISH_VERSION.to_le_bytes());
+    bytes[6] = variant_code(variant);
+    u64::from_le_bytes(bytes)
+}
+
+fn invalid(msg: String) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, msg)
+}
+
+/// Identifies a file's contents: its inode (on Unix) and length.
+#[cfg(unix)]
+fn file_identity(metadata: &Metadata) -> (u64, u64, u64) {
+    (metadata.dev(), metadata.ino(), metadata.len())
+}
+
+#[cfg(not(unix))]
+fn file_identity(metadata: &Metadata) -> (u64, u64, u64) {
+    (0, 0, metadata.len())
+}
+
+/// A shared memory mapping of a file, as 64-bit words.
+struct Mapping {
+    ptr: *mut u8,
+    words: usize,
+}
+
+impl Mapping {
+    #[cfg(unix)]
+    fn new(file: &File, words: usize, writable: bool) -> io::Result<Mapping> {
+        let prot = if writable { libc::PROT_READ | libc::PROT_WRITE } else { libc::PROT_READ };
+        let ptr = unsafe {
+            libc::mmap(ptr::null_mut(), words * 8, prot, libc::MAP_SHARED, file.as_raw_fd(), 0)
+        };
+        if ptr == libc::MAP_FAILED {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(Mapping { ptr: ptr as *mut u8, words })
+    }
+
+    #[cfg(not(unix))]
+    fn new(_file: &File, _words: usize, _writable: bool) -> io::Result<Mapping> {
+        Err(io::Error::new(io::ErrorKind::Unsupported,
+                           "publishing heartbeats requires a Unix platform"))
+    }
+
+    /// The mapping is page-aligned, and only accessed atomically.
+    fn words(&self) -> &[AtomicU64] {
+        unsafe { slice::from_raw_parts(self.ptr as *const AtomicU64, self.words) }
+    }
+}
+
+impl Drop for Mapping {
+    fn drop(&mut self) {
+        #[cfg(unix)]
+        unsafe {
+            libc::munmap(self.ptr as *mut libc::c_void, self.words * 8);
+        }
+    }
+}
+
+// The mapping is only accessed through atomics.
+unsafe impl Send for Mapping {}
+unsafe impl Sync for Mapping {}
+
+/// Publishes heartbeat records to a memory-mapped file.
+///
+/// Heartbeats create one with `publish_to`, but it can also publish records from other
+/// sources, e.g. a `Replay`. There must only be one publisher per file.
+pub struct Publisher {
+    map: Mapping,
+    window_size: usize,
+}
+
+impl Publisher {
+    /// Create (or replace) the file at `path` to publish records of `variant`, keeping a window
+    /// of `window_size` records.
+    ///
+    /// The state is initialized in a temporary file in the same directory, then renamed over
+    /// `path`, so readers of a previous file keep a valid mapping until they reopen (see
+    /// `PublishedReader::is_stale`).
+    /// On Linux, a path under `/dev/shm` avoids writing the state to disk.
+    pub fn create<P: AsRef<Path>>(path: P,
+                                  variant: LogVariant,
+                                  window_size: usize) -> io::Result<Publisher> {
+        let path = path.as_ref();
+        let mut temp = OsString::from(path.as_os_str());
+        temp.push(format!(".{}.tmp", process::id()));
+        let temp = PathBuf::from(temp);
+        let res = Publisher::init(&temp, variant, window_size.max(1))
+            .and_then(|publisher| fs::rename(&temp, path).map(|_| publisher));
+        if res.is_err() {
+            let _ = fs::remove_file(&temp);
+        }
+        res
+    }
+
+    fn init(path: &Path, variant: LogVariant, window_size: usize) -> io::Result<Publisher> {
+        let file = OpenOptions::new().read(true).write(true).create(true).truncate(true)
+            .open(path)?;
+        let words = HEADER_WORDS + RECORD_WORDS * (window_size + 1);
+        file.set_len(words as u64 * 8)?;
+        let map = Mapping::new(&file, words, true)?;
+        let w = map.words();
+        w[WORD_WINDOW_SIZE].store(window_size as u64, Ordering::Relaxed);
+        w[WORD_PID].store(process::id() as u64, Ordering::Relaxed);
+        w[WORD_RECORD_WORDS].store(RECORD_WORDS as u64, Ordering::Relaxed);
+        // readers check the magic word first, so write it last
+        w[WORD_MAGIC].store(magic_word(variant), Ordering::Release);
+        Ok(Publisher { map, window_size })
+    }
+
+    /// Publish `record` as the latest record, adding it to the window.
+    pub fn publish(&self, record: &LogRecord) {
+        let w = self.map.words();
+        let seq = w[WORD_SEQ].load(Ordering::Relaxed);
+        w[WORD_SEQ].store(seq + 1, Ordering::Relaxed);
+        fence(Ordering::Release);
+        let slot = 1 + (record.id % self.window_size as u64) as usize;
+        for &start in &[HEADER_WORDS, HEADER_WORDS + RECORD_WORDS * slot] {
+            let mut i = start;
+            visit_values(record, |v| {
+                let word = match v {
+                    Value::Int(i) => i,
+                    Value::Float(f) => f.to_bits(),
+                };
+                w[i].store(word, Ordering::Relaxed);
+                i += 1;
+            });
+        }
+        let count = w[WORD_COUNT].load(Ordering::Relaxed);
+        w[WORD_COUNT].store((count + 1).min(self.window_size as u64), Ordering::Relaxed);
+        w[WORD_SEQ].store(seq + 2, Ordering::Release);
+    }
+}
+
+/// A consistent snapshot of published heartbeat state.
+#[derive(Debug, Clone, PartialEq)]
+pub struct PublishedState {
+    /// The number of records published.
+    pub updates: u64,
+    /// The most recent record, with the heartbeat's current statistics.
+    pub latest: Option<LogRecord>,
+    /// The records in the window, oldest first.
+    pub window: Vec<LogRecord>,
+}
+
+/// Observes heartbeat state published by another process (or thread).
+///
+/// The layout and publisher are read when the reader is opened. If the publisher restarts, it
+/// replaces the file, so check `is_stale` and reopen to follow it.
+pub struct PublishedReader {
+    map: Mapping,
+    path: PathBuf,
+    identity: (u64, u64, u64),
+    variant: LogVariant,
+    window_size: usize,
+    record_words: usize,
+    pid: u32,
+}
+
+impl PublishedReader {
+    /// Open the state published at `path`.
+    pub fn open<P: AsRef<Path>>(path: P) -> io::Result<PublishedReader> {
+        let path = path.as_ref().to_path_buf();
+        let file = File::open(&path)?;
+        let metadata = file.metadata()?;
+        let identity = file_identity(&metadata);
+        let words = (metadata.len() / 8) as usize;
+        if words < HEADER_WORDS {
+            return Err(invalid("file is too small for published heartbeat state".to_string()));
+        }
+        let map = Mapping::new(&file, words, false)?;
+        let w = map.words();
+        let header = w[WORD_MAGIC].load(Ordering::Acquire).to_le_bytes();
+        if header[..4] != PUBLISH_MAGIC {
+            return Err(invalid(format!("magic bytes {:?}", &header[..4])));
+        }
+        let version = u16::from_le_bytes([header[4], header[5]]);
+        if version == 0 || version > PUBLISH_VERSION {
+            return Err(invalid(format!("unsupported version {}", version)));
+        }
+        let variant = variant_from_code(header[6])
+            .ok_or_else(|| invalid(format!("unknown variant {}", header[6])))?;
+        let window_size = w[WORD_WINDOW_SIZE].load(Ordering::Relaxed) as usize;
+        let record_words = w[WORD_RECORD_WORDS].load(Ordering::Relaxed) as usize;
+        let needed = window_size.checked_add(1)
+            .and_then(|n| n.checked_mul(record_words))
+            .and_then(|n| n.checked_add(HEADER_WORDS));
+        if window_size == 0 || record_words < variant.columns().len() ||
+            needed.is_none_or(|n| n > words) {
+            return Err(invalid(format!("bad layout: window size {}, {} words per record",
+                                       window_size, record_words)));
+        }
+        let pid = w[WORD_PID].load(Ordering::Relaxed) as u32;
+        Ok(PublishedReader { map, path, identity, variant, window_size, record_words, pid })
+    }
+
+    /// Whether the file at the opened path has been replaced (e.g. by a restarted publisher),
+    /// resized, or removed since the reader was opened, so it should be reopened.
+    pub fn is_stale(&self) -> bool {
+        fs::metadata(&self.path).map_or(true, |m| file_identity(&m) != self.identity)
+    }
+
+    /// The variant of the publishing heartbeat.
+    pub fn variant(&self) -> LogVariant {
+        self.variant
+    }
+
+    /// The size of the published window.
+    pub fn window_size(&self) -> usize {
+        self.window_size
+    }
+
+    /// The ID of the publishing process.
+    pub fn pid(&self) -> u32 {
+        self.pid
+    }
+
+    /// The number of records published so far, without reading them.
+    pub fn updates(&self) -> u64 {
+        self.map.words()[WORD_SEQ].load(Ordering::Acquire) / 2
+    }
+
+    fn record(&self, start: usize) -> LogRecord {
+        let w = self.map.words();
+        let mut i = start;
+        record_from_values(self.variant, || {
+            i += 1;
+            w[i - 1].load(Ordering::Relaxed)
+        })
+    }
+
+    /// Read a consistent snapshot of the published state, retrying while it's being updated.
+    ///
+    /// Fails with `io::ErrorKind::TimedOut` if the publisher seems to have stopped mid-update.
+    pub fn read(&self) -> io::Result<PublishedState> {
+        let w = self.map.words();
+        for _ in 0..READ_ATTEMPTS {
+            let seq = w[WORD_SEQ].load(Ordering::Acquire);
+            if seq % 2 == 1 {
+                thread::yield_now();
+                continue;
+            }
+            let count = w[WORD_COUNT].load(Ordering::Relaxed).min(self.window_size as u64);
+            let latest = if seq > 0 { Some(self.record(HEADER_WORDS)) } else { None };
+            let window = match latest {
+                Some(ref l) if count > 0 => {
+                    let first = (l.id + 1).saturating_sub(count);
+                    (first..l.id + 1).map(|id| {
+                        let slot = 1 + (id % self.window_size as u64) as usize;
+                        self.record(HEADER_WORDS + self.record_words * slot)
+                    }).collect()
+                }
+                _ => Vec::new(),
+            };
+            fence(Ordering::Acquire);
+            if w[WORD_SEQ].load(Ordering::Relaxed) == seq {
+                return Ok(PublishedState { updates: seq / 2, latest, window });
+            }
+        }
+        Err(io::Error::new(io::ErrorKind::TimedOut, "published state is stuck mid-update"))
+    }
+}
+
+#[cfg(all(test, unix))]
+mod test {
+    use super::*;
+    use hbs_acc_pow::HeartbeatAccPow;
+    use std::env;
+    use std::fs;
+    use std::path::PathBuf;
+    use std::sync::Arc;
+    use std::sync::atomic::AtomicBool;
+
+    fn temp_path(name: &str) -> PathBuf {
+        env::temp_dir().join(format!("heartbeats-simple-publish-{}-{}", name, process::id()))
+    }
+
+    #[test]
+    fn test_heartbeat() {
+        let path = temp_path("heartbeat");
+        let mut hb = HeartbeatAccPow::new(3, None, None).unwrap();
+        hb.heartbeat(0, 1, 0, 1000, 1, 0, 10);
+        hb.publish_to(&path).unwrap();
+        let reader = PublishedReader::open(&path).unwrap();
+        assert_eq!((reader.variant(), reader.window_size()), (LogVariant::AccPow, 3));
+        assert_eq!(reader.pid(), process::id());
+        // existing records are published too
+        assert_eq!(reader.read().unwrap().window.len(), 1);
+        for tag in 1..5 {
+            hb.heartbeat(tag, 2, tag * 1000, (tag + 1) * 1000, 1, tag * 10, (tag + 1) * 10);
+        }
+        let state = reader.read().unwrap();
+        assert_eq!(state.updates, 5);
+        assert_eq!(reader.updates(), 5);
+        let expected: Vec<LogRecord> = hb.records().map(LogRecord::from).collect();
+        assert_eq!(state.window, expected);
+        assert_eq!(state.latest.unwrap(), LogRecord::from(hb.latest().unwrap()));
+        assert_eq!(state.latest.unwrap().power.unwrap().window_energy, 30);
+        drop(hb);
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_empty() {
+        let path = temp_path("empty");
+        let _publisher = Publisher::create(&path, LogVariant::Pow, 4).unwrap();
+        let state = PublishedReader::open(&path).unwrap().read().unwrap();
+        assert_eq!(state, PublishedState { updates: 0, latest: None, window: vec![] });
+        fs::write(&path, b"not published state").unwrap();
+        assert!(PublishedReader::open(&path).is_err());
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_restart() {
+        let path = temp_path("restart");
+        let publisher = Publisher::create(&path, LogVariant::Heartbeat, 4).unwrap();
+        publisher.publish(&LogRecord::default());
+        let reader = PublishedReader::open(&path).unwrap();
+        assert!(!reader.is_stale());
+        drop(publisher);
+        let publisher = Publisher::create(&path, LogVariant::Pow, 2).unwrap();
+        assert!(reader.is_stale());
+        // the old mapping stays readable
+        assert_eq!(reader.read().unwrap().updates, 1);
+        let reader = PublishedReader::open(&path).unwrap();
+        assert_eq!((reader.variant(), reader.window_size()), (LogVariant::Pow, 2));
+        assert_eq!(reader.read().unwrap().updates, 0);
+        drop(publisher);
+        fs::remove_file(&path).unwrap();
+        assert!(reader.is_stale());
+    }
+
+    #[test]
+    fn test_concurrent() {
+        let path = temp_path("concurrent");
+        let publisher = Publisher::create(&path, LogVariant::Heartbeat, 8).unwrap();
+        let reader = PublishedReader::open(&path).unwrap();
+        let done = Arc::new(AtomicBool::new(false));
+        let writer = {
+            let done = done.clone();
+            thread::spawn(move || {
+                for id in 0..20000 {
+                    let r = LogRecord { id, user_tag: id * 2, work: id * 3, ..Default::default() };
+                    publisher.publish(&r);
+                }
+                done.store(true, Ordering::SeqCst);
+            })
+        };
+        while !done.load(Ordering::SeqCst) {
+            let state = reader.read().unwrap();
+            if let Some(latest) = state.latest {
+                assert_eq!(state.window.last(), Some(&latest));
+                for (i, r) in state.window.iter().enumerate() {
+                    assert_eq!(r.id, latest.id + 1 + i as u64 - state.window.len() as u64);
+                    assert_eq!((r.user_tag, r.work), (r.id * 2, r.id * 3));
+                }
+            }
+        }
+        writer.join().unwrap();
+        assert_eq!(reader.read().unwrap().updates, 20000);
+        fs::remove_file(&path).unwrap();
+    }
+}
@@ -0,0 +1,638 @@
+//! A pure-Rust implementation of heartbeats-simple, with the same types and functions as
+//! `heartbeats-simple-sys`.
+//!
+//! Heartbeats are never logged natively: `log_fd` is stored but ignored.
+#![allow(non_camel_case_types)]
+
+use libc::c_int;
+use std::ptr;
+
+const ONE_MILLION: f64 = 1000000.0;
+const ONE_BILLION: f64 = 1000000000.0;
+
+/// Unsigned global and window data.
+#[repr(C)]
+pub struct heartbeat_udata {
+    pub global: u64,
+    pub window: u64,
+}
+
+/// Rate data.
+#[repr(C)]
+pub struct heartbeat_rates {
+    pub global: f64,
+    pub window: f64,
+    pub instant: f64,
+}
+
+/// State for window buffer
+#[repr(C)]
+pub struct heartbeat_window_state {
+    pub buffer_index: u64,
+    pub read_index: u64,
+    pub window_size: u64,
+    pub log_fd: c_int,
+}
+
+/// Typedef for the window completion callback function.
+pub type heartbeat_window_complete = Option<extern "C" fn(*const heartbeat_context)>;
+
+/// A heartbeat record with current rates.
+#[repr(C)]
+pub struct heartbeat_record {
+    pub id: u64,
+    pub user_tag: u64,
+    pub work: u64,
+    pub wd: heartbeat_udata,
+    pub start_time: u64,
+    pub end_time: u64,
+    pub td: heartbeat_udata,
+    pub perf: heartbeat_rates,
+}
+
+/// A `heartbeat_context` is used for tracking performance of recurring jobs.
+#[repr(C)]
+pub struct heartbeat_context {
+    pub ws: heartbeat_window_state,
+    pub window_buffer: *mut heartbeat_record,
+    pub counter: u64,
+    pub lock: c_int,
+    pub hwc_callback: heartbeat_window_complete,
+    pub td: heartbeat_udata,
+    pub wd: heartbeat_udata,
+}
+
+/// Typedef for the window completion callback function.
+pub type heartbeat_acc_window_complete = Option<extern "C" fn(*const heartbeat_acc_context)>;
+
+/// A heartbeat record with current rates (performance and accuracy).
+#[repr(C)]
+pub struct heartbeat_acc_record {
+    pub id: u64,
+    pub user_tag: u64,
+    pub work: u64,
+    pub wd: heartbeat_udata,
+    pub start_time: u64,
+    pub end_time: u64,
+    pub td: heartbeat_udata,
+    pub perf: heartbeat_rates,
+    pub accuracy: u64,
+    pub ad: heartbeat_udata,
+    pub acc: heartbeat_rates,
+}
+
+/// A `heartbeat_acc_context` is used for tracking performance/accuracy of recurring jobs.
+#[repr(C)]
+pub struct heartbeat_acc_context {
+    pub ws: heartbeat_window_state,
+    pub window_buffer: *mut heartbeat_acc_record,
+    pub counter: u64,
+    pub lock: c_int,
+    pub hwc_callback: heartbeat_acc_window_complete,
+    pub td: heartbeat_udata,
+    pub wd: heartbeat_udata,
+    pub ad: heartbeat_udata,
+}
+
+/// Typedef for the window completion callback function.
+pub type heartbeat_pow_window_complete = Option<extern "C" fn(*const heartbeat_pow_context)>;
+
+/// A heartbeat record with current rates (performance and power).
+#[repr(C)]
+pub struct heartbeat_pow_record {
+    pub id: u64,
+    pub user_tag: u64,
+    pub work: u64,
+    pub wd: heartbeat_udata,
+    pub start_time: u64,
+    pub end_time: u64,
+    pub td: heartbeat_udata,
+    pub perf: heartbeat_rates,
+    pub start_energy: u64,
+    pub end_energy: u64,
+    pub ed: heartbeat_udata,
+    pub pwr: heartbeat_rates,
+}
+
+/// A `heartbeat_pow_context` is used for tracking performance/power of recurring jobs.
+#[repr(C)]
+pub struct heartbeat_pow_context {
+    pub ws: heartbeat_window_state,
+    pub window_buffer: *mut heartbeat_pow_record,
+    pub counter: u64,
+    pub lock: c_int,
+    pub hwc_callback: heartbeat_pow_window_complete,
+    pub td: heartbeat_udata,
+    pub wd: heartbeat_udata,
+    pub ed: heartbeat_udata,
+}
+
+/// Typedef for the window completion callback function.
+pub type heartbeat_acc_pow_window_complete =
+    Option<extern "C" fn(*const heartbeat_acc_pow_context)>;
+
+/// A heartbeat record with current rates (performance, accuracy, and power).
+#[repr(C)]
+pub struct heartbeat_acc_pow_record {
+    pub id: u64,
+    pub user_tag: u64,
+    pub work: u64,
+    pub wd: heartbeat_udata,
+    pub start_time: u64,
+    pub end_time: u64,
+    pub td: heartbeat_udata,
+    pub perf: heartbeat_rates,
+    pub accuracy: u64,
+    pub ad: heartbeat_udata,
+    pub acc: heartbeat_rates,
+    pub start_energy: u64,
+    pub end_energy: u64,
+    pub ed: heartbeat_udata,
+    pub pwr: heartbeat_rates,
+}
+
+/// A `heartbeat_acc_pow_context` is used for tracking performance/accuracy/power of recurring
+/// jobs.
+#[repr(C)]
+pub struct heartbeat_acc_pow_context {
+    pub ws: heartbeat_window_state,
+    pub window_buffer: *mut heartbeat_acc_pow_record,
+    pub counter: u64,
+    pub lock: c_int,
+    pub hwc_callback: heartbeat_acc_pow_window_complete,
+    pub td: heartbeat_udata,
+    pub wd: heartbeat_udata,
+    pub ad: heartbeat_udata,
+    pub ed: heartbeat_udata,
+}
+
+fn new_udata() -> heartbeat_udata {
+    heartbeat_udata { global: 0, window: 0 }
+}
+
+fn copy_udata(data: &heartbeat_udata) -> heartbeat_udata {
+    heartbeat_udata { global: data.global, window: data.window }
+}
+
+/// Add `delta` to global data, and recompute window data given the global data of the record
+/// leaving the window.
+fn accumulate(data: &mut heartbeat_udata, delta: u64, old_global: u64) {
+    data.global = data.global.wrapping_add(delta);
+    data.window = data.global.wrapping_sub(old_global);
+}
+
+/// Durations used to compute rates, in seconds.
+struct Seconds {
+    total: f64,
+    window: f64,
+    instant: f64,
+}
+
+/// Compute rates from global and window data and an instant amount.
+fn rates(data: &heartbeat_udata, instant: f64, seconds: &Seconds) -> heartbeat_rates {
+    heartbeat_rates {
+        global: data.global as f64 / seconds.total,
+        window: data.window as f64 / seconds.window,
+        instant: instant / seconds.instant,
+    }
+}
+
+/// Implements `heartbeat_init` for the context and record types.
+macro_rules! heartbeat_init {
+    ($hb:expr, $window_size:expr, $window_buffer:expr, $log_fd:expr, $hwc_callback:expr,
+     $($extra:ident),*) => {{
+        if $hb.is_null() || $window_buffer.is_null() || $window_size == 0 {
+            return -1;
+        }
+        let hb = &mut *$hb;
+        hb.ws = heartbeat_window_state {
+            buffer_index: 0,
+            read_index: 0,
+            window_size: $window_size,
+            log_fd: $log_fd,
+        };
+        hb.window_buffer = $window_buffer;
+        // cheap way to set initial values to 0 (necessary for managing window data)
+        ptr::write_bytes(hb.window_buffer, 0, $window_size as usize);
+        hb.counter = 0;
+        hb.lock = 0;
+        hb.hwc_callback = $hwc_callback;
+        hb.td = new_udata();
+        hb.wd = new_udata();
+        $(hb.$extra = new_udata();)*
+        0
+    }};
+}
+
+/// Records time, work and performance for a heartbeat, returning the time spent.
+///
+/// Must be called before recording any other data, as the record being written is also the old
+/// record leaving the window.
+macro_rules! record_perf {
+    ($hb:expr, $r:expr, $user_tag:expr, $work:expr, $start_time:expr, $end_time:expr) => {{
+        $r.id = $hb.counter;
+        $r.user_tag = $user_tag;
+        let delta_time = $end_time.wrapping_sub($start_time) as i64;
+        accumulate(&mut $hb.td, delta_time as u64, $r.td.global);
+        accumulate(&mut $hb.wd, $work, $r.wd.global);
+        $r.work = $work;
+        $r.wd = copy_udata(&$hb.wd);
+        $r.start_time = $start_time;
+        $r.end_time = $end_time;
+        $r.td = copy_udata(&$hb.td);
+        let seconds = Seconds {
+            total: $hb.td.global as f64 / ONE_BILLION,
+            window: $hb.td.window as f64 / ONE_BILLION,
+            instant: delta_time as f64 / ONE_BILLION,
+        };
+        $r.perf = rates(&$r.wd, $work as f64, &seconds);
+        seconds
+    }};
+}
+
+/// Records accuracy for a heartbeat.
+macro_rules! record_acc {
+    ($hb:expr, $r:expr, $accuracy:expr, $seconds:expr) => {{
+        accumulate(&mut $hb.ad, $accuracy, $r.ad.global);
+        $r.accuracy = $accuracy;
+        $r.ad = copy_udata(&$hb.ad);
+        $r.acc = rates(&$r.ad, $accuracy as f64, &$seconds);
+    }};
+}
+
+/// Records energy and power for a heartbeat.
+macro_rules! record_pow {
+    ($hb:expr, $r:expr, $start_energy:expr, $end_energy:expr, $seconds:expr) => {{
+        let delta_energy = $end_energy.wrapping_sub($start_energy) as i64;
+        accumulate(&mut $hb.ed, delta_energy as u64, $r.ed.global);
+        $r.start_energy = $start_energy;
+        $r.end_energy = $end_energy;
+        $r.ed = copy_udata(&$hb.ed);
+        let pwr = rates(&$r.ed, delta_energy as f64, &$seconds);
+        $r.pwr = heartbeat_rates {
+            global: pwr.global / ONE_MILLION,
+            window: pwr.window / ONE_MILLION,
+            instant: pwr.instant / ONE_MILLION,
+        };
+    }};
+}
+
+/// Advances the window state after a heartbeat, issuing the callback if the window is complete.
+macro_rules! advance_window {
+    ($hb:expr) => {{
+        $hb.counter += 1;
+        $hb.ws.read_index = $hb.ws.buffer_index;
+        $hb.ws.buffer_index += 1;
+        if $hb.ws.buffer_index % $hb.ws.window_size == 0 {
+            if let Some(cb) = $hb.hwc_callback {
+                cb($hb);
+            }
+            $hb.ws.buffer_index = 0;
+        }
+    }};
+}
+
+/// Gets the context and the record to write, or returns if the context is invalid.
+macro_rules! current_record {
+    ($hb:expr) => {{
+        if $hb.is_null() || (*$hb).window_buffer.is_null() {
+            return;
+        }
+        let hb = &mut *$hb;
+        let r = &mut *hb.window_buffer.offset(hb.ws.buffer_index as isize);
+        (hb, r)
+    }};
+}
+
+/// Defines getters that read from the context.
+macro_rules! context_getters {
+    ($ctx:ident, $($name:ident: $ret:ty = $($field:ident).+;)*) => {
+        $(pub unsafe fn $name(hb: *const $ctx) -> $ret {
+            (*hb).$($field).+
+        })*
+    };
+}
+
+/// Defines getters that read from the most recent record.
+macro_rules! record_getters {
+    ($ctx:ident, $($name:ident: $ret:ty = $($field:ident).+;)*) => {
+        $(pub unsafe fn $name(hb: *const $ctx) -> $ret {
+            let hb = &*hb;
+            (*hb.window_buffer.offset(hb.ws.read_index as isize)).$($field).+
+        })*
+    };
+}
+
+pub unsafe fn heartbeat_init(hb: *mut heartbeat_context,
+                             window_size: u64,
+                             window_buffer: *mut heartbeat_record,
+                             log_fd: c_int,
+                             hwc_callback: heartbeat_window_complete) -> c_int {
+    heartbeat_init!(hb, window_size, window_buffer, log_fd, hwc_callback,)
+}
+
+pub unsafe fn heartbeat(hb: *mut heartbeat_context,
+                        user_tag: u64,
+                        work: u64,
+                        start_time: u64,
+                        end_time: u64) {
+    let (hb, r) = current_record!(hb);
+    record_perf!(hb, r, user_tag, work, start_time, end_time);
+    advance_window!(hb);
+}
+
+context_getters!(heartbeat_context,
+                 hb_get_window_size: u64 = ws.window_size;
+                 hb_get_global_time: u64 = td.global;
+                 hb_get_window_time: u64 = td.window;
+                 hb_get_global_work: u64 = wd.global;
+                 hb_get_window_work: u64 = wd.window;);
+
+record_getters!(heartbeat_context,
+                hb_get_user_tag: u64 = user_tag;
+                hb_get_global_perf: f64 = perf.global;
+                hb_get_window_perf: f64 = perf.window;
+                hb_get_instant_perf: f64 = perf.instant;);
+
+pub unsafe fn heartbeat_acc_init(hb: *mut heartbeat_acc_context,
+                                 window_size: u64,
+                                 window_buffer: *mut heartbeat_acc_record,
+                                 log_fd: c_int,
+                                 hwc_callback: heartbeat_acc_window_complete) -> c_int {
+    heartbeat_init!(hb, window_size, window_buffer, log_fd, hwc_callback, ad)
+}
+
+pub unsafe fn heartbeat_acc(hb: *mut heartbeat_acc_context,
+                            user_tag: u64,
+                            work: u64,
+                            start_time: u64,
+                            end_time: u64,
+                            accuracy: u64) {
+    let (hb, r) = current_record!(hb);
+    let seconds = record_perf!(hb, r, user_tag, work, start_time, end_time);
+    record_acc!(hb, r, accuracy, seconds);
+    advance_window!(hb);
+}
+
+context_getters!(heartbeat_acc_context,
+                 hb_acc_get_window_size: u64 = ws.window_size;
+                 hb_acc_get_global_time: u64 = td.global;
+                 hb_acc_get_window_time: u64 = td.window;
+                 hb_acc_get_global_work: u64 = wd.global;
+                 hb_acc_get_window_work: u64 = wd.window;
+                 hb_acc_get_global_accuracy: u64 = ad.global;
+                 hb_acc_get_window_accuracy: u64 = ad.window;);
+
+record_getters!(heartbeat_acc_context,
+                hb_acc_get_user_tag: u64 = user_tag;
+                hb_acc_get_global_perf: f64 = perf.global;
+                hb_acc_get_window_perf: f64 = perf.window;
+                hb_acc_get_instant_perf: f64 = perf.instant;
+                hb_acc_get_global_accuracy_rate: f64 = acc.global;
+                hb_acc_get_window_accuracy_rate: f64 = acc.window;
+                hb_acc_get_instant_accuracy_rate: f64 = acc.instant;);
+
+pub unsafe fn heartbeat_pow_init(hb: *mut heartbeat_pow_context,
+                                 window_size: u64,
+                                 window_buffer: *mut heartbeat_pow_record,
+                                 log_fd: c_int,
+                                 hwc_callback: heartbeat_pow_window_complete) -> c_int {
+    heartbeat_init!(hb, window_size, window_buffer, log_fd, hwc_callback, ed)
+}
+
+pub unsafe fn heartbeat_pow(hb: *mut heartbeat_pow_context,
+                            user_tag: u64,
+                            work: u64,
+                            start_time: u64,
+                            end_time: u64,
+                            start_energy: u64,
+                            end_energy: u64) {
+    let (hb, r) = current_record!(hb);
+    let seconds = record_perf!(hb, r, user_tag, work, start_time, end_time);
+    record_pow!(hb, r, start_energy, end_energy, seconds);
+    advance_window!(hb);
+}
+
+context_getters!(heartbeat_pow_context,
+                 hb_pow_get_window_size: u64 = ws.window_size;
+                 hb_pow_get_global_time: u64 = td.global;
+                 hb_pow_get_window_time: u64 = td.window;
+                 hb_pow_get_global_work: u64 = wd.global;
+                 hb_pow_get_window_work: u64 = wd.window;
+                 hb_pow_get_global_energy: u64 = ed.global;
+                 hb_pow_get_window_energy: u64 = ed.window;);
+
+record_getters!(heartbeat_pow_context,
+                hb_pow_get_user_tag: u64 = user_tag;
+                hb_pow_get_global_perf: f64 = perf.global;
+                hb_pow_get_window_perf: f64 = perf.window;
+                hb_pow_get_instant_perf: f64 = perf.instant;
+                hb_pow_get_global_power: f64 = pwr.global;
+                hb_pow_get_window_power: f64 = pwr.window;
+                hb_pow_get_instant_power: f64 = pwr.instant;);
+
+pub unsafe fn heartbeat_acc_pow_init(hb: *mut heartbeat_acc_pow_context,
+                                     window_size: u64,
+                                     window_buffer: *mut heartbeat_acc_pow_record,
+                                     log_fd: c_int,
+                                     hwc_callback: heartbeat_acc_pow_window_complete) -> c_int {
+    heartbeat_init!(hb, window_size, window_buffer, log_fd, hwc_callback, ad, ed)
+}
+
+#[allow(clippy::too_many_arguments)]
+pub unsafe fn heartbeat_acc_pow(hb: *mut heartbeat_acc_pow_context,
+                                user_tag: u64,
+                                work: u64,
+                                start_time: u64,
+                                end_time: u64,
+                                accuracy: u64,
+                                start_energy: u64,
+                                end_energy: u64) {
+    let (hb, r) = current_record!(hb);
+    let seconds = record_perf!(hb, r, user_tag, work, start_time, end_time);
+    record_acc!(hb, r, accuracy, seconds);
+    record_pow!(hb, r, start_energy, end_energy, seconds);
+    advance_window!(hb);
+}
+
+context_getters!(heartbeat_acc_pow_context,
+                 hb_acc_pow_get_window_size: u64 = ws.window_size;
+                 hb_acc_pow_get_global_time: u64 = td.global;
+                 hb_acc_pow_get_window_time: u64 = td.window;
+                 hb_acc_pow_get_global_work: u64 = wd.global;
+                 hb_acc_pow_get_window_work: u64 = wd.window;
+                 hb_acc_pow_get_global_accuracy: u64 = ad.global;
+                 hb_acc_pow_get_window_accuracy: u64 = ad.window;
+                 hb_acc_pow_get_global_energy: u64 = ed.global;
+                 hb_acc_pow_get_window_energy: u64 = ed.window;);
+
+record_getters!(heartbeat_acc_pow_context,
+                hb_acc_pow_get_user_tag: u64 = user_tag;
+                hb_acc_pow_get_global_perf: f64 = perf.global;
+                hb_acc_pow_get_window_perf: f64 = perf.window;
+                hb_acc_pow_get_instant_perf: f64 = perf.instant;
+                hb_acc_pow_get_global_accuracy_rate: f64 = acc.global;
+                hb_acc_pow_get_window_accuracy_rate: f64 = acc.window;
+                hb_acc_pow_get_instant_accuracy_rate: f64 = acc.instant;
+                hb_acc_pow_get_global_power: f64 = pwr.global;
+                hb_acc_pow_get_window_power: f64 = pwr.window;
+                hb_acc_pow_get_instant_power: f64 = pwr.instant;);
+
+#[cfg(all(test, feature = "heartbeats-simple-sys"))]
+mod test {
+    use super::*;
+    use heartbeats_simple_sys as sys;
+    use std::mem::MaybeUninit;
+
+    const WINDOW_SIZES: [u64; 3] = [1, 3, 20];
+
+    /// Simple deterministic pseudo-random inputs.
+    struct Lcg(u64);
+
+    impl Lcg {
+        fn next(&mut self, max: u64) -> u64 {
+            self.0 = self.0.wrapping_mul(6364136223846793005).wrapping_add(1442695040888963407);
+            (self.0 >> 33) % max
+        }
+    }
+
+    /// A heartbeat's inputs: user_tag, work, start/end time, accuracy, start/end energy.
+    type Input = (u64, u64, u64, u64, u64, u64, u64);
+
+    fn inputs() -> Vec<Input> {
+        let mut rng = Lcg(42);
+        let (mut time, mut energy) = (1000, 5000);
+        (0..200).map(|i| {
+            let start_time = time;
+            // include some zero-length heartbeats
+            time += if rng.next(10) == 0 { 0 } else { rng.next(10_000_000) };
+            let start_energy = energy;
+            energy += rng.next(1_000_000);
+            (i, rng.next(10), start_time, time, rng.next(100), start_energy, energy)
+        }).collect()
+    }
+
+    fn same(a: f64, b: f64) -> bool {
+        a == b || (a.is_nan() && b.is_nan())
+    }
+
+    macro_rules! init {
+        ($ctx:ty, $rec:ty, $init:path, $ws:expr) => {{
+            let mut buf: Vec<$rec> = Vec::with_capacity($ws as usize);
+            let mut hb = MaybeUninit::<$ctx>::uninit();
+            unsafe {
+                assert_eq!($init(hb.as_mut_ptr(), $ws, buf.as_mut_ptr(), -1, None), 0);
+                (hb.assume_init(), buf)
+            }
+        }};
+    }
+
+    macro_rules! assert_same {
+        ($pure:expr, $native:expr, $($getter:ident),*) => {
+            $(unsafe {
+                let (p, n) = ($getter($pure), sys::$getter($native));
+                assert!(same(p as f64, n as f64), "{}: {} != {}", stringify!($getter), p, n);
+            })*
+        };
+    }
+
+    #[test]
+    fn test_heartbeat() {
+        for &ws in &WINDOW_SIZES {
+            let (mut p, _pb) = init!(heartbeat_context, heartbeat_record, heartbeat_init, ws);
+            let (mut n, _nb) = init!(sys::heartbeat_context, sys::heartbeat_record,
+                                     sys::heartbeat_init, ws);
+            for &(tag, work, st, et, _, _, _) in &inputs() {
+                unsafe {
+                    heartbeat(&mut p, tag, work, st, et);
+                    sys::heartbeat(&mut n, tag, work, st, et);
+                }
+                assert_eq!(p.ws.buffer_index, n.ws.buffer_index);
+                assert_same!(&p, &n, hb_get_window_size, hb_get_user_tag,
+                             hb_get_global_time, hb_get_window_time,
+                             hb_get_global_work, hb_get_window_work,
+                             hb_get_global_perf, hb_get_window_perf, hb_get_instant_perf);
+            }
+        }
+    }
+
+    #[test]
+    fn test_heartbeat_acc() {
+        for &ws in &WINDOW_SIZES {
+            let (mut p, _pb) = init!(heartbeat_acc_context, heartbeat_acc_record,
+                                     heartbeat_acc_init, ws);
+            let (mut n, _nb) = init!(sys::heartbeat_acc_context, sys::heartbeat_acc_record,
+                                     sys::heartbeat_acc_init, ws);
+            for &(tag, work, st, et, acc, _, _) in &inputs() {
+                unsafe {
+                    heartbeat_acc(&mut p, tag, work, st, et, acc);
+                    sys::heartbeat_acc(&mut n, tag, work, st, et, acc);
+                }
+                assert_eq!(p.ws.buffer_index, n.ws.buffer_index);
+                assert_same!(&p, &n, hb_acc_get_window_size, hb_acc_get_user_tag,
+                             hb_acc_get_global_time, hb_acc_get_window_time,
+                             hb_acc_get_global_work, hb_acc_get_window_work,
+                             hb_acc_get_global_perf, hb_acc_get_window_perf,
+                             hb_acc_get_instant_perf,
+                             hb_acc_get_global_accuracy, hb_acc_get_window_accuracy,
+                             hb_acc_get_global_accuracy_rate, hb_acc_get_window_accuracy_rate,
+                             hb_acc_get_instant_accuracy_rate);
+            }
+        }
+    }
+
+    #[test]
+    fn test_heartbeat_pow() {
+        for &ws in &WINDOW_SIZES {
+            let (mut p, _pb) = init!(heartbeat_pow_context, heartbeat_pow_record,
+                                     heartbeat_pow_init, ws);
+            let (mut n, _nb) = init!(sys::heartbeat_pow_context, sys::heartbeat_pow_record,
+                                     sys::heartbeat_pow_init, ws);
+            for &(tag, work, st, et, _, se, ee) in &inputs() {
+                unsafe {
+                    heartbeat_pow(&mut p, tag, work, st, et, se, ee);
+                    sys::heartbeat_pow(&mut n, tag, work, st, et, se, ee);
+                }
+                assert_eq!(p.ws.buffer_index, n.ws.buffer_index);
+                assert_same!(&p, &n, hb_pow_get_window_size, hb_pow_get_user_tag,
+                             hb_pow_get_global_time, hb_pow_get_window_time,
+                             hb_pow_get_global_work, hb_pow_get_window_work,
+                             hb_pow_get_global_perf, hb_pow_get_window_perf,
+                             hb_pow_get_instant_perf,
+                             hb_pow_get_global_energy, hb_pow_get_window_energy,
+                             hb_pow_get_global_power, hb_pow_get_window_power,
+                             hb_pow_get_instant_power);
+            }
+        }
+    }
+
+    #[test]
+    fn test_heartbeat_acc_pow() {
+        for &ws in &WINDOW_SIZES {
+            let (mut p, _pb) = init!(heartbeat_acc_pow_context, heartbeat_acc_pow_record,
+                                     heartbeat_acc_pow_init, ws);
+            let (mut n, _nb) = init!(sys::heartbeat_acc_pow_context,
+                                     sys::heartbeat_acc_pow_record,
+                                     sys::heartbeat_acc_pow_init, ws);
+            for &(tag, work, st, et, acc, se, ee) in &inputs() {
+                unsafe {
+                    heartbeat_acc_pow(&mut p, tag, work, st, et, acc, se, ee);
+                    sys::heartbeat_acc_pow(&mut n, tag, work, st, et, acc, se, ee);
+                }
+                assert_eq!(p.ws.buffer_index, n.ws.buffer_index);
+                assert_same!(&p, &n, hb_acc_pow_get_window_size, hb_acc_pow_get_user_tag,
+                             hb_acc_pow_get_global_time, hb_acc_pow_get_window_time,
+                             hb_acc_pow_get_global_work, hb_acc_pow_get_window_work,
+                             hb_acc_pow_get_global_perf, hb_acc_pow_get_window_perf,
+                             hb_acc_pow_get_instant_perf,
+                             hb_acc_pow_get_global_accuracy, hb_acc_pow_get_window_accuracy,
+                             hb_acc_pow_get_global_accuracy_rate,
+                             hb_acc_pow_get_window_accuracy_rate,
+                             hb_acc_pow_get_instant_accuracy_rate,
+                             hb_acc_pow_get_global_energy, hb_acc_pow_get_window_energy,
+                             hb_acc_pow_get_global_power, hb_acc_pow_get_window_power,
+                             hb_acc_pow_get_instant_power);
+            }
+        }
+    }
+}
@@ -0,0 +1,374 @@
+use std::sync::{Arc, Mutex, MutexGuard};
+use error::HeartbeatError;
+use hbs::{Heartbeat, HeartbeatStats};
+use hbs_acc::{HeartbeatAcc, HeartbeatAccStats};
+use hbs_pow::{HeartbeatPow, HeartbeatPowStats};
+use hbs_acc_pow::{HeartbeatAccPow, HeartbeatAccPowStats};
+use hbs_common::{HasAccuracy, HasPower, HeartbeatMonitor};
+
+/// A heartbeat that can be shared between threads.
+///
+/// Clones refer to the same heartbeat, so each worker thread can hold its own handle.
+/// The heartbeat is locked only while a heartbeat is issued or data is read, so timestamps and
+/// energy should be taken before calling in, keeping work outside the lock.
+/// If a thread panics while holding the lock (e.g., in a window callback), the heartbeat remains
+/// usable by other threads.
+pub struct Shared<H>(Arc<Mutex<H>>);
+
+/// A `Heartbeat` that can be shared between threads.
+pub type SharedHeartbeat = Shared<Heartbeat>;
+/// A `HeartbeatAcc` that can be shared between threads.
+pub type SharedHeartbeatAcc = Shared<HeartbeatAcc>;
+/// A `HeartbeatPow` that can be shared between threads.
+pub type SharedHeartbeatPow = Shared<HeartbeatPow>;
+/// A `HeartbeatAccPow` that can be shared between threads.
+pub type SharedHeartbeatAccPow = Shared<HeartbeatAccPow>;
+
+impl<H> Clone for Shared<H> {
+    fn clone(&self) -> Shared<H> {
+        Shared(self.0.clone())
+    }
+}
+
+impl<H> From<H> for Shared<H> {
+    fn from(hb: H) -> Shared<H> {
+        Shared::new(hb)
+    }
+}
+
+impl<H> Shared<H> {
+    /// Share an existing heartbeat.
+    pub fn new(hb: H) -> Shared<H> {
+        Shared(Arc::new(Mutex::new(hb)))
+    }
+
+    /// Lock the heartbeat for exclusive access, e.g. to configure it or read its records.
+    ///
+    /// Heartbeats issued from other threads block until the guard is dropped.
+    pub fn lock(&self) -> MutexGuard<'_, H> {
+        self.0.lock().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+impl<H: HeartbeatMonitor> Shared<H> {
+    /// Log any records not yet written, then flush the log (see `log_to_buffer_index` on the
+    /// heartbeat types).
+    pub fn log_to_buffer_index(&self) -> Result<(), HeartbeatError> {
+        self.lock().log_to_buffer_index()
+    }
+
+    pub fn get_window_size(&self) -> u64 {
+        self.lock().get_window_size()
+    }
+
+    pub fn get_user_tag(&self) -> u64 {
+        self.lock().get_user_tag()
+    }
+
+    pub fn get_global_time(&self) -> u64 {
+        self.lock().get_global_time()
+    }
+
+    pub fn get_window_time(&self) -> u64 {
+        self.lock().get_window_time()
+    }
+
+    pub fn get_global_work(&self) -> u64 {
+        self.lock().get_global_work()
+    }
+
+    pub fn get_window_work(&self) -> u64 {
+        self.lock().get_window_work()
+    }
+
+    pub fn get_global_perf(&self) -> f64 {
+        self.lock().get_global_perf()
+    }
+
+    pub fn get_window_perf(&self) -> f64 {
+        self.lock().get_window_perf()
+    }
+
+    pub fn get_instant_perf(&self) -> f64 {
+        self.lock().get_instant_perf()
+    }
+}
+
+impl<H: HasAccuracy> Shared<H> {
+    pub fn get_global_accuracy(&self) -> u64 {
+        self.lock().get_global_accuracy()
+    }
+
+    pub fn get_window_accuracy(&self) -> u64 {
+        self.lock().get_window_accuracy()
+    }
+
+    pub fn get_global_accuracy_rate(&self) -> f64 {
+        self.lock().get_global_accuracy_rate()
+    }
+
+    pub fn get_window_accuracy_rate(&self) -> f64 {
+        self.lock().get_window_accuracy_rate()
+    }
+
+    pub fn get_instant_accuracy_rate(&self) -> f64 {
+        self.lock().get_instant_accuracy_rate()
+    }
+}
+
+impl<H: HasPower> Shared<H> {
+    pub fn get_global_energy(&self) -> u64 {
+        self.lock().get_global_energy()
+    }
+
+    pub fn get_window_energy(&self) -> u64 {
+        self.lock().get_window_energy()
+    }
+
+    pub fn get_global_power(&self) -> f64 {
+        self.lock().get_global_power()
+    }
+
+    pub fn get_window_power(&self) -> f64 {
+        self.lock().get_window_power()
+    }
+
+    pub fn get_instant_power(&self) -> f64 {
+        self.lock().get_instant_power()
+    }
+}
+
+impl Shared<Heartbeat> {
+    /// Issue a heartbeat (see `Heartbeat::heartbeat`).
+    pub fn heartbeat(&self, tag: u64, work: u64, start_time: u64, end_time: u64) {
+        self.lock().heartbeat(tag, work, start_time, end_time)
+    }
+
+    /// Validate and issue a heartbeat (see `Heartbeat::try_heartbeat`).
+    pub fn try_heartbeat(&self,
+                         tag: u64,
+                         work: u64,
+                         start_time: u64,
+                         end_time: u64) -> Result<(), HeartbeatError> {
+        self.lock().try_heartbeat(tag, work, start_time, end_time)
+    }
+
+    /// Issue a heartbeat for the work done since the previous heartbeat ended, from any thread
+    /// (see `Heartbeat::heartbeat_now`).
+    pub fn heartbeat_now(&self, tag: u64, work: u64) {
+        self.lock().heartbeat_now(tag, work)
+    }
+
+    /// Get the current time from the heartbeat's clock, in nanoseconds.
+    pub fn now(&self) -> u64 {
+        self.lock().now()
+    }
+
+    /// Get a snapshot of all the current statistics.
+    pub fn stats(&self) -> HeartbeatStats {
+        self.lock().stats()
+    }
+}
+
+impl Shared<HeartbeatAcc> {
+    /// Issue a heartbeat (see `HeartbeatAcc::heartbeat`).
+    pub fn heartbeat(&self, tag: u64, work: u64, start_time: u64, end_time: u64, accuracy: u64) {
+        self.lock().heartbeat(tag, work, start_time, end_time, accuracy)
+    }
+
+    /// Validate and issue a heartbeat (see `HeartbeatAcc::try_heartbeat`).
+    pub fn try_heartbeat(&self,
+                         tag: u64,
+                         work: u64,
+                         start_time: u64,
+                         end_time: u64,
+                         accuracy: u64) -> Result<(), HeartbeatError> {
+        self.lock().try_heartbeat(tag, work, start_time, end_time, accuracy)
+    }
+
+    /// Issue a heartbeat for the work done since the previous heartbeat ended, from any thread
+    /// (see `HeartbeatAcc::heartbeat_now`).
+    pub fn heartbeat_now(&self, tag: u64, work: u64, accuracy: u64) {
+        self.lock().heartbeat_now(tag, work, accuracy)
+    }
+
+    /// Get the current time from the heartbeat's clock, in nanoseconds.
+    pub fn now(&self) -> u64 {
+        self.lock().now()
+    }
+
+    /// Get a snapshot of all the current statistics.
+    pub fn stats(&self) -> HeartbeatAccStats {
+        self.lock().stats()
+    }
+}
+
+impl Shared<HeartbeatPow> {
+    /// Issue a heartbeat (see `HeartbeatPow::heartbeat`).
+    pub fn heartbeat(&self,
+                     tag: u64,
+                     work: u64,
+                     start_time: u64,
+                     end_time: u64,
+                     start_energy: u64,
+                     end_energy: u64) {
+        self.lock().heartbeat(tag, work, start_time, end_time, start_energy, end_energy)
+    }
+
+    /// Validate and issue a heartbeat (see `HeartbeatPow::try_heartbeat`).
+    pub fn try_heartbeat(&self,
+                         tag: u64,
+                         work: u64,
+                         start_time: u64,
+                         end_time: u64,
+                         start_energy: u64,
+                         end_energy: u64) -> Result<(), HeartbeatError> {
+        self.lock().try_heartbeat(tag, work, start_time, end_time, start_energy, end_energy)
+    }
+
+    /// Issue a heartbeat for the work done since the previous heartbeat ended, from any thread
+    /// (see `HeartbeatPow::heartbeat_now`).
+    pub fn heartbeat_now(&self, tag: u64, work: u64, start_energy: u64, end_energy: u64) {
+        self.lock().heartbeat_now(tag, work, start_energy, end_energy)
+    }
+
+    /// Like `heartbeat_now`, but with energy read by the heartbeat's energy reader (see
+    /// `HeartbeatPow::heartbeat_now_sampled`).
+    pub fn heartbeat_now_sampled(&self, tag: u64, work: u64) -> Result<(), HeartbeatError> {
+        self.lock().heartbeat_now_sampled(tag, work)
+    }
+
+    /// Get the current time from the heartbeat's clock, in nanoseconds.
+    pub fn now(&self) -> u64 {
+        self.lock().now()
+    }
+
+    /// Read the heartbeat's energy reader, in microjoules.
+    pub fn read_energy(&self) -> Result<u64, HeartbeatError> {
+        self.lock().read_energy()
+    }
+
+    /// Get a snapshot of all the current statistics.
+    pub fn stats(&self) -> HeartbeatPowStats {
+        self.lock().stats()
+    }
+}
+
+impl Shared<HeartbeatAccPow> {
+    /// Issue a heartbeat (see `HeartbeatAccPow::heartbeat`).
+    #[allow(clippy::too_many_arguments)]
+    pub fn heartbeat(&self,
+                     tag: u64,
+                     work: u64,
+                     start_time: u64,
+                     end_time: u64,
+                     accuracy: u64,
+                     start_energy: u64,
+                     end_energy: u64) {
+        self.lock().heartbeat(tag, work, start_time, end_time, accuracy, start_energy, end_energy)
+    }
+
+    /// Validate and issue a heartbeat (see `HeartbeatAccPow::try_heartbeat`).
+    #[allow(clippy::too_many_arguments)]
+    pub fn try_heartbeat(&self,
+                         tag: u64,
+                         work: u64,
+                         start_time: u64,
+                         end_time: u64,
+                         accuracy: u64,
+                         start_energy: u64,
+                         end_energy: u64) -> Result<(), HeartbeatError> {
+        self.lock().try_heartbeat(tag, work, start_time, end_time, accuracy, start_energy,
+                                  end_energy)
+    }
+
+    /// Issue a heartbeat for the work done since the previous heartbeat ended, from any thread
+    /// (see `HeartbeatAccPow::heartbeat_now`).
+    pub fn heartbeat_now(&self,
+                         tag: u64,
+                         work: u64,
+                         accuracy: u64,
+                         start_energy: u64,
+                         end_energy: u64) {
+        self.lock().heartbeat_now(tag, work, accuracy, start_energy, end_energy)
+    }
+
+    /// Like `heartbeat_now`, but with energy read by the heartbeat's energy reader (see
+    /// `HeartbeatAccPow::heartbeat_now_sampled`).
+    pub fn heartbeat_now_sampled(&self, tag: u64, work: u64, accuracy: u64)
+        -> Result<(), HeartbeatError> {
+        self.lock().heartbeat_now_sampled(tag, work, accuracy)
+    }
+
+    /// Get the current time from the heartbeat's clock, in nanoseconds.
+    pub fn now(&self) -> u64 {
+        self.lock().now()
+    }
+
+    /// Read the heartbeat's energy reader, in microjoules.
+    pub fn read_energy(&self) -> Result<u64, HeartbeatError> {
+        self.lock().read_energy()
+    }
+
+    /// Get a snapshot of all the current statistics.
+    pub fn stats(&self) -> HeartbeatAccPowStats {
+        self.lock().stats()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use energy::TestEnergy;
+    use hbs_common::TestLog;
+    use std::collections::HashSet;
+    use std::thread;
+
+    fn assert_send_sync<T: Send + Sync>() {}
+
+    #[test]
+    fn test_send_sync() {
+        assert_send_sync::<SharedHeartbeat>();
+        assert_send_sync::<SharedHeartbeatAcc>();
+        assert_send_sync::<SharedHeartbeatPow>();
+        assert_send_sync::<SharedHeartbeatAccPow>();
+    }
+
+    #[test]
+    fn test_threads() {
+        let log = TestLog::default();
+        let shared = SharedHeartbeat::new(Heartbeat::new(10, None, log.sink()).unwrap());
+        let workers: Vec<_> = (0..4).map(|t| {
+            let hb = shared.clone();
+            thread::spawn(move || {
+                for i in 0..100 {
+                    let start_time = hb.now();
+                    hb.heartbeat(t * 100 + i, 2, start_time, start_time + 1000);
+                }
+            })
+        }).collect();
+        for w in workers {
+            w.join().unwrap();
+        }
+        shared.log_to_buffer_index().unwrap();
+        assert_eq!(shared.get_global_work(), 800);
+        assert_eq!(shared.stats().window_work, 20);
+        let tags: HashSet<u64> = log.contents().lines().skip(1)
+            .map(|l| l.split_whitespace().nth(1).unwrap().parse().unwrap())
+            .collect();
+        assert_eq!(tags, (0..400).collect());
+    }
+
+    #[test]
+    fn test_sampled() {
+        let energy = TestEnergy::default();
+        let mut hb = HeartbeatPow::new(5, None, None).unwrap();
+        hb.set_energy_reader(energy.clone()).unwrap();
+        let shared = SharedHeartbeatPow::from(hb);
+        let worker = shared.clone();
+        energy.advance(1000);
+        thread::spawn(move || worker.heartbeat_now_sampled(0, 1).unwrap()).join().unwrap();
+        assert_eq!(shared.get_global_energy(), 1000);
+        assert_eq!(shared.lock().len(), 1);
+    }
+}
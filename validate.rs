@@ -0,0 +1,126 @@
+use error::HeartbeatError;
+
+/// How `try_heartbeat` handles invalid input.
+#[derive(Default)]
+pub enum ValidationPolicy {
+    /// Return an `InvalidInput` error without issuing the heartbeat.
+    #[default]
+    Reject,
+    /// Adjust the input to the nearest valid values and issue the heartbeat.
+    Clamp,
+    /// Call the closure with an `InvalidInput` error, then issue the heartbeat unchanged.
+    Warn(Box<dyn FnMut(&HeartbeatError) + Send>),
+}
+
+impl ValidationPolicy {
+    /// Handle invalid input, returning whether the input should be clamped.
+    fn invalid(&mut self, msg: String) -> Result<bool, HeartbeatError> {
+        match *self {
+            ValidationPolicy::Reject => Err(HeartbeatError::InvalidInput(msg)),
+            ValidationPolicy::Clamp => Ok(true),
+            ValidationPolicy::Warn(ref mut f) => {
+                f(&HeartbeatError::InvalidInput(msg));
+                Ok(false)
+            }
+        }
+    }
+
+    /// Check that a heartbeat doesn't end before it starts or before the previous one ended.
+    ///
+    /// Heartbeats may overlap, so a `start_time` before the previous heartbeat's end is allowed.
+    pub(crate) fn check_time(&mut self,
+                             start_time: u64,
+                             end_time: &mut u64,
+                             prev_end_time: Option<u64>) -> Result<(), HeartbeatError> {
+        if *end_time < start_time &&
+            self.invalid(format!("end_time {} is before start_time {}", end_time, start_time))? {
+            *end_time = start_time;
+        }
+        if let Some(prev) = prev_end_time {
+            if *end_time < prev &&
+                self.invalid(format!("end_time {} is before the previous heartbeat's end_time {}",
+                                     end_time, prev))? {
+                *end_time = prev;
+            }
+        }
+        Ok(())
+    }
+
+    /// Check that energy doesn't decrease during a heartbeat or since the previous one ended.
+    ///
+    /// As with time, a `start_energy` below the previous heartbeat's end energy is allowed.
+    /// `prev_end_energy` should be `None` for counters that are expected to wrap around.
+    pub(crate) fn check_energy(&mut self,
+                               start_energy: u64,
+                               end_energy: &mut u64,
+                               prev_end_energy: Option<u64>) -> Result<(), HeartbeatError> {
+        if *end_energy < start_energy &&
+            self.invalid(format!("end_energy {} is less than start_energy {}",
+                                 end_energy, start_energy))? {
+            *end_energy = start_energy;
+        }
+        if let Some(prev) = prev_end_energy {
+            if *end_energy < prev &&
+                self.invalid(format!("end_energy {} is less than the previous heartbeat's \
+                                      end_energy {}", end_energy, prev))? {
+                *end_energy = prev;
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::sync::{Arc, Mutex};
+
+    #[test]
+    fn test_reject() {
+        let mut policy = ValidationPolicy::Reject;
+        let (start, mut end) = (100, 200);
+        policy.check_time(start, &mut end, Some(150)).unwrap();
+        // overlapping the previous heartbeat is fine
+        policy.check_time(start, &mut end, Some(200)).unwrap();
+        policy.check_energy(start, &mut end, Some(200)).unwrap();
+        assert_eq!(end, 200);
+        let (start, mut end) = (200, 100);
+        match policy.check_time(start, &mut end, None) {
+            Err(HeartbeatError::InvalidInput(msg)) => {
+                assert_eq!(msg, "end_time 100 is before start_time 200")
+            }
+            _ => panic!("Expected InvalidInput"),
+        }
+        let (start, mut end) = (100, 200);
+        assert!(policy.check_energy(start, &mut end, Some(300)).is_err());
+    }
+
+    #[test]
+    fn test_clamp() {
+        let mut policy = ValidationPolicy::Clamp;
+        let (start, mut end) = (200, 100);
+        policy.check_time(start, &mut end, None).unwrap();
+        assert_eq!(end, 200);
+        let (start, mut end) = (100, 200);
+        policy.check_time(start, &mut end, Some(300)).unwrap();
+        assert_eq!(end, 300);
+        let (start, mut end) = (100, 200);
+        policy.check_energy(start, &mut end, Some(300)).unwrap();
+        assert_eq!(end, 300);
+    }
+
+    #[test]
+    fn test_warn() {
+        let warnings = Arc::new(Mutex::new(Vec::new()));
+        let w = warnings.clone();
+        let mut policy = ValidationPolicy::Warn(Box::new(move |e| {
+            w.lock().unwrap().push(e.to_string())
+        }));
+        let (start, mut end) = (100, 200);
+        policy.check_time(start, &mut end, Some(300)).unwrap();
+        assert_eq!(end, 200);
+        assert_eq!(*warnings.lock().unwrap(),
+                   vec!["Invalid heartbeat input: end_time 200 is before the previous heartbeat's \
+                         end_time 300"]);
+    }
+}